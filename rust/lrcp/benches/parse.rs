@@ -0,0 +1,161 @@
+// Compares the hand-written packet parser against the regex parser it
+// replaced, which is reproduced here as the baseline.
+#[macro_use]
+extern crate lazy_static;
+
+use std::{collections::VecDeque, io::Read, ops::Range};
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use regex::bytes::{Captures, Regex};
+
+use lrcp::packet::{parse_message, Data, Message};
+
+mod baseline {
+    use super::*;
+
+    const MAX_NUMBER: u64 = 2147483647;
+
+    // The fields are only built, not read, just as the parser used to do.
+    #[allow(dead_code)]
+    pub enum Message {
+        Connect(u64),
+        Data(u64, u64, Vec<u8>, VecDeque<Range<usize>>),
+        Ack(u64, u64),
+        Close(u64),
+    }
+
+    lazy_static! {
+        static ref CONNECT: Regex = Regex::new(r"^/connect/(?P<session>[0-9]+)/$").unwrap();
+        static ref CLOSE: Regex = Regex::new(r"^/close/(?P<session>[0-9]+)/$").unwrap();
+        static ref DATA: Regex =
+            Regex::new(r"(?s-u)^/data/(?P<session>[0-9]+)/(?P<position>[0-9]+)/(?P<data>.*)/$")
+                .unwrap();
+        static ref ACK: Regex =
+            Regex::new(r"^/ack/(?P<session>[0-9]+)/(?P<position>[0-9]+)/$").unwrap();
+    }
+
+    fn pluck(caps: &Captures, name: &str) -> Option<u64> {
+        let value = std::str::from_utf8(caps.name(name)?.as_bytes()).ok()?;
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value <= MAX_NUMBER)
+    }
+
+    fn unescaped_ranges(body: &[u8], start: usize) -> Option<VecDeque<Range<usize>>> {
+        let mut ranges = VecDeque::with_capacity(1);
+        let mut offset = 0;
+        let mut i = 0;
+        while i < body.len() {
+            match body[i] {
+                b'/' => return None,
+                b'\\' => {
+                    match body.get(i + 1) {
+                        Some(b'/') | Some(b'\\') => {}
+                        _ => return None,
+                    }
+                    if offset < i {
+                        ranges.push_back(start + offset..start + i);
+                    }
+                    offset = i + 1;
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+        if offset < body.len() {
+            ranges.push_back(start + offset..start + body.len());
+        }
+        Some(ranges)
+    }
+
+    pub fn parse_message(data: Vec<u8>) -> Option<Message> {
+        if data.starts_with(b"/connect/") {
+            let caps = CONNECT.captures(&data)?;
+            Some(Message::Connect(pluck(&caps, "session")?))
+        } else if data.starts_with(b"/data/") {
+            let caps = DATA.captures(&data)?;
+            let session = pluck(&caps, "session")?;
+            let position = pluck(&caps, "position")?;
+            let cap = caps.name("data")?;
+            let ranges = unescaped_ranges(cap.as_bytes(), cap.start())?;
+            Some(Message::Data(session, position, data, ranges))
+        } else if data.starts_with(b"/ack/") {
+            let caps = ACK.captures(&data)?;
+            Some(Message::Ack(
+                pluck(&caps, "session")?,
+                pluck(&caps, "position")?,
+            ))
+        } else if data.starts_with(b"/close/") {
+            let caps = CLOSE.captures(&data)?;
+            Some(Message::Close(pluck(&caps, "session")?))
+        } else {
+            None
+        }
+    }
+
+    pub fn payload(source: &[u8], ranges: &VecDeque<Range<usize>>, buf: &mut Vec<u8>) {
+        for range in ranges {
+            buf.extend_from_slice(&source[range.clone()]);
+        }
+    }
+}
+
+fn packets(mix: &str) -> Vec<Vec<u8>> {
+    let connect = b"/connect/1234567/".to_vec();
+    let ack = b"/ack/1234567/1048576/".to_vec();
+    let plain = Message::Data(Data::new(1234567, 1048576, vec![b'x'; 900]))
+        .encode()
+        .unwrap();
+    let escaped = Message::Data(Data::new(1234567, 1048576, b"ab/\\\n".repeat(90)))
+        .encode()
+        .unwrap();
+    match mix {
+        "connect" => vec![connect],
+        "ack" => vec![ack],
+        "data" => vec![plain, escaped],
+        // Roughly what a busy server sees: mostly acks and data.
+        "mixed" => vec![connect, ack.clone(), ack, plain, escaped],
+        _ => unreachable!(),
+    }
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for mix in ["connect", "ack", "data", "mixed"] {
+        let packets = packets(mix);
+        // The receive loop hands the parser packets that share its buffer,
+        // so each one costs a reference count rather than a copy.
+        let shared: Vec<Bytes> = packets.iter().cloned().map(Bytes::from).collect();
+        group.bench_with_input(BenchmarkId::new("hand", mix), &shared, |b, packets| {
+            let mut buf = Vec::with_capacity(1000);
+            b.iter(|| {
+                for packet in packets {
+                    if let Message::Data(mut data) = parse_message(packet.clone()).unwrap() {
+                        buf.clear();
+                        data.read_to_end(&mut buf).unwrap();
+                    }
+                }
+                black_box(&buf);
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("regex", mix), &packets, |b, packets| {
+            let mut buf = Vec::with_capacity(1000);
+            b.iter(|| {
+                for packet in packets {
+                    let message = baseline::parse_message(packet.clone()).unwrap();
+                    if let baseline::Message::Data(_, _, source, ranges) = message {
+                        buf.clear();
+                        baseline::payload(&source, &ranges, &mut buf);
+                    }
+                }
+                black_box(&buf);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use clap::Parser;
+use lrcp::stream::LrcpStream;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+/// Bridges stdin and stdout to an LRCP session, like netcat for LRCP.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The server's host.
+    host: String,
+    /// The server's udp port.
+    port: u16,
+    /// Once stdin ends, how many milliseconds of quiet from the server to wait
+    /// for before closing the session.
+    #[arg(long, default_value_t = 1000)]
+    linger: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+    let stream = LrcpStream::connect(&format!("{}:{}", args.host, args.port)).await?;
+    let (mut reader, mut writer) = io::split(stream);
+    let mut sending = tokio::spawn(async move {
+        io::copy(&mut io::stdin(), &mut writer).await?;
+        Ok::<_, io::Error>(writer)
+    });
+    let mut stdout = io::stdout();
+    let mut buf = vec![0u8; 4096];
+    // We have the writer back once stdin ends, and give it up to close.
+    let mut writer = None;
+    let mut closing = false;
+    loop {
+        let len = if closing {
+            reader.read(&mut buf).await?
+        } else if writer.is_none() {
+            tokio::select! {
+                sent = &mut sending => {
+                    writer = Some(sent??);
+                    continue;
+                }
+                len = reader.read(&mut buf) => len?,
+            }
+        } else {
+            match time::timeout(Duration::from_millis(args.linger), reader.read(&mut buf)).await {
+                Ok(len) => len?,
+                Err(_) => {
+                    // The server has gone quiet, so we close the session and
+                    // read until it's done.
+                    if let Some(mut writer) = writer.take() {
+                        writer.shutdown().await?;
+                    }
+                    closing = true;
+                    continue;
+                }
+            }
+        };
+        if len == 0 {
+            break;
+        }
+        stdout.write_all(&buf[..len]).await?;
+        stdout.flush().await?;
+    }
+    Ok(())
+}
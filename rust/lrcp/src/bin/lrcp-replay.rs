@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use lrcp::{
+    capture::{read_capture, replay, Direction},
+    reverse::reverse_lines,
+};
+
+/// Replays a capture recorded by `lrcp --record` against a fresh line
+/// reversal server, under the config it was recorded with, on a simulated
+/// clock, printing what the server sends and where that first differs from
+/// the recording.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The capture to replay.
+    capture: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .init();
+    let capture = read_capture(&args.capture)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()?;
+    let replayed = runtime.block_on(replay(&capture, |stream| async {
+        if let Err(err) = reverse_lines(stream).await {
+            tracing::error!(?err, "reversing lines");
+        }
+    }));
+    let recorded: Vec<_> = capture
+        .records
+        .iter()
+        .filter(|record| record.direction == Direction::Outbound)
+        .collect();
+    let mut diverged = false;
+    for (i, record) in replayed.iter().enumerate() {
+        println!("{}", record);
+        if diverged {
+            continue;
+        }
+        let expected = recorded.get(i);
+        if expected.map(|e| (e.addr, &e.datagram)) != Some((record.addr, &record.datagram)) {
+            diverged = true;
+            match expected {
+                Some(expected) => println!("diverged; the recording has {}", expected),
+                None => println!("diverged; the recording ends"),
+            }
+        }
+    }
+    if !diverged && recorded.len() > replayed.len() {
+        println!("diverged; the replay ends before the recording");
+    }
+    Ok(())
+}
@@ -0,0 +1,479 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    future::Future,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{mpsc, Arc},
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+use tokio::{
+    io::ReadBuf,
+    time::{self, Instant},
+};
+
+use crate::{
+    server::{AddressPolicy, Config},
+    sim::Network,
+    socket::{self, Socket},
+    stream::{LrcpListener, LrcpStream},
+};
+
+// A capture starts with the config the server ran under, so a replay can run
+// under the same one, laid out big-endian as:
+//
+//   u64  retransmission timeout in microseconds
+//   u64  session expiry timeout in microseconds
+//   u64  send window
+//   u64  receive window
+//   u8   0 to reject packets from a session's old address, 1 to migrate
+//
+// Then comes a sequence of records, each laid out big-endian as:
+//
+//   u64  microseconds since the capture began
+//   u8   0 for inbound, 1 for outbound
+//   u8   length of the peer address, then the address as text
+//   u32  length of the datagram, then the datagram
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub at: Duration,
+    pub direction: Direction,
+    // Whom the datagram came from or went to.
+    pub addr: SocketAddr,
+    pub datagram: Vec<u8>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Inbound => "<-",
+            Direction::Outbound => "->",
+        };
+        write!(
+            f,
+            "{:>12.3?} {} {} {}",
+            self.at,
+            arrow,
+            self.addr,
+            self.datagram.escape_ascii()
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub config: Config,
+    pub records: Vec<Record>,
+}
+
+pub fn write_config(writer: &mut impl Write, config: &Config) -> io::Result<()> {
+    writer.write_all(&(config.retransmission_timeout.as_micros() as u64).to_be_bytes())?;
+    writer.write_all(&(config.session_expiry_timeout.as_micros() as u64).to_be_bytes())?;
+    writer.write_all(&(config.send_window as u64).to_be_bytes())?;
+    writer.write_all(&(config.receive_window as u64).to_be_bytes())?;
+    writer.write_all(&[match config.address_policy {
+        AddressPolicy::Reject => 0,
+        AddressPolicy::Migrate => 1,
+    }])
+}
+
+pub fn read_config(reader: &mut impl Read) -> io::Result<Config> {
+    let mut read_u64 = || -> io::Result<u64> {
+        let mut n = [0u8; 8];
+        reader.read_exact(&mut n)?;
+        Ok(u64::from_be_bytes(n))
+    };
+    let retransmission_timeout = Duration::from_micros(read_u64()?);
+    let session_expiry_timeout = Duration::from_micros(read_u64()?);
+    let send_window = usize::try_from(read_u64()?).map_err(|_| invalid("send window too large"))?;
+    let receive_window =
+        usize::try_from(read_u64()?).map_err(|_| invalid("receive window too large"))?;
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let address_policy = match byte[0] {
+        0 => AddressPolicy::Reject,
+        1 => AddressPolicy::Migrate,
+        _ => return Err(invalid("unknown address policy")),
+    };
+    Ok(Config {
+        retransmission_timeout,
+        session_expiry_timeout,
+        send_window,
+        receive_window,
+        address_policy,
+    })
+}
+
+pub fn write_record(writer: &mut impl Write, record: &Record) -> io::Result<()> {
+    let addr = record.addr.to_string();
+    writer.write_all(&(record.at.as_micros() as u64).to_be_bytes())?;
+    writer.write_all(&[match record.direction {
+        Direction::Inbound => 0,
+        Direction::Outbound => 1,
+    }])?;
+    writer.write_all(&[addr.len() as u8])?;
+    writer.write_all(addr.as_bytes())?;
+    writer.write_all(&(record.datagram.len() as u32).to_be_bytes())?;
+    writer.write_all(&record.datagram)
+}
+
+// Reads the next record, or None at the end of the capture.
+pub fn read_record(reader: &mut impl Read) -> io::Result<Option<Record>> {
+    let mut at = [0u8; 8];
+    match reader.read_exact(&mut at) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let direction = match byte[0] {
+        0 => Direction::Inbound,
+        1 => Direction::Outbound,
+        _ => return Err(invalid("unknown direction")),
+    };
+    reader.read_exact(&mut byte)?;
+    let mut addr = vec![0u8; byte[0] as usize];
+    reader.read_exact(&mut addr)?;
+    let addr = String::from_utf8(addr)
+        .ok()
+        .and_then(|addr| addr.parse().ok())
+        .ok_or_else(|| invalid("invalid address"))?;
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut datagram = vec![0u8; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut datagram)?;
+    Ok(Some(Record {
+        at: Duration::from_micros(u64::from_be_bytes(at)),
+        direction,
+        addr,
+        datagram,
+    }))
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+pub fn read_capture(path: impl AsRef<Path>) -> io::Result<Capture> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let config = read_config(&mut reader)?;
+    let mut records = Vec::new();
+    while let Some(record) = read_record(&mut reader)? {
+        records.push(record);
+    }
+    Ok(Capture { config, records })
+}
+
+// A socket that records every datagram it sends or receives, for a server
+// running under the given config. A thread writes the records, so the socket
+// never waits on the disk.
+#[derive(Debug)]
+pub struct Recorder {
+    socket: Arc<dyn Socket>,
+    started_at: Instant,
+    entries: mpsc::Sender<Entry>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Record(Record),
+    // Asks the writer to say when it has written everything before this.
+    Sync(mpsc::Sender<()>),
+}
+
+impl Recorder {
+    pub fn create(
+        socket: Arc<dyn Socket>,
+        path: impl AsRef<Path>,
+        config: &Config,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_config(&mut writer, config)?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut write = || -> io::Result<()> {
+                while let Ok(entry) = rx.recv() {
+                    let mut entry = Some(entry);
+                    while let Some(next) = entry {
+                        match next {
+                            Entry::Record(record) => write_record(&mut writer, &record)?,
+                            Entry::Sync(synced) => {
+                                writer.flush()?;
+                                let _ = synced.send(());
+                            }
+                        }
+                        entry = rx.try_recv().ok();
+                    }
+                    // Flush whenever we catch up, so a crash loses little.
+                    writer.flush()?;
+                }
+                Ok(())
+            };
+            if let Err(err) = write() {
+                tracing::error!(?err, "writing capture");
+            }
+        });
+        Ok(Self {
+            socket,
+            started_at: Instant::now(),
+            entries: tx,
+        })
+    }
+
+    // Blocks until everything recorded so far is in the file.
+    pub fn sync(&self) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "capture writer stopped");
+        self.entries.send(Entry::Sync(tx)).map_err(|_| stopped())?;
+        rx.recv().map_err(|_| stopped())
+    }
+
+    fn record(&self, direction: Direction, addr: SocketAddr, datagram: &[u8]) {
+        let record = Record {
+            at: self.started_at.elapsed(),
+            direction,
+            addr,
+            datagram: datagram.to_vec(),
+        };
+        // If the writer has failed, it has already said so.
+        let _ = self.entries.send(Entry::Record(record));
+    }
+}
+
+impl Socket for Recorder {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let sent = self.socket.poll_send_to(cx, buf, target);
+        if let Poll::Ready(Ok(len)) = sent {
+            self.record(Direction::Outbound, target, &buf[..len]);
+        }
+        sent
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        let filled = buf.filled().len();
+        let received = self.socket.poll_recv_from(cx, buf);
+        if let Poll::Ready(Ok(addr)) = received {
+            self.record(Direction::Inbound, addr, &buf.filled()[filled..]);
+        }
+        received
+    }
+}
+
+// Feeds a capture's inbound datagrams to a fresh server running app under the
+// recorded config, each at the same time after the start as it was recorded,
+// and returns what the server sent. Run on a paused clock, the replay is
+// deterministic and takes no real time.
+pub async fn replay<F, Fut>(capture: &Capture, app: F) -> Vec<Record>
+where
+    F: Fn(LrcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let Capture { config, records } = capture;
+    let config = *config;
+    let started_at = Instant::now();
+    let network = Network::new(0, Default::default());
+    let server = Arc::new(network.bind());
+    let server_addr = server.local_addr().expect("address on network");
+    let mut listener = LrcpListener::from_socket(server, config).expect("serve on network");
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(app(stream));
+        }
+    });
+    // Each recorded peer gets its own socket on the network, and whatever the
+    // server sends it is recorded under the peer's original address.
+    let (sent_tx, sent_rx) = mpsc::channel();
+    let mut peers: HashMap<SocketAddr, Arc<dyn Socket>> = HashMap::new();
+    let mut last = started_at;
+    for record in records {
+        if record.direction != Direction::Inbound {
+            continue;
+        }
+        let peer = peers.entry(record.addr).or_insert_with(|| {
+            let peer: Arc<dyn Socket> = Arc::new(network.bind());
+            tokio::spawn(listen(
+                peer.clone(),
+                record.addr,
+                started_at,
+                sent_tx.clone(),
+            ));
+            peer
+        });
+        last = started_at + record.at;
+        time::sleep_until(last).await;
+        if let Err(err) = socket::send_to(peer.as_ref(), &record.datagram, server_addr).await {
+            tracing::error!(?err, "replaying datagram");
+        }
+    }
+    // Give the server as long as a session could last to finish up.
+    time::sleep_until(last + config.session_expiry_timeout).await;
+    sent_rx.try_iter().collect()
+}
+
+async fn listen(
+    peer: Arc<dyn Socket>,
+    addr: SocketAddr,
+    started_at: Instant,
+    sent: mpsc::Sender<Record>,
+) {
+    let mut buf = [0u8; 1000];
+    while let Ok((len, _)) = socket::recv_from(peer.as_ref(), &mut buf).await {
+        let record = Record {
+            at: started_at.elapsed(),
+            direction: Direction::Outbound,
+            addr,
+            datagram: buf[..len].to_vec(),
+        };
+        if sent.send(record).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{reverse::reverse_lines, sim::Conditions};
+
+    use super::*;
+
+    #[test]
+    fn test_record_roundtrip() {
+        let records = vec![
+            Record {
+                at: Duration::from_micros(1234),
+                direction: Direction::Inbound,
+                addr: "10.0.0.1:9000".parse().unwrap(),
+                datagram: b"/connect/1/".to_vec(),
+            },
+            Record {
+                at: Duration::from_secs(3),
+                direction: Direction::Outbound,
+                addr: SocketAddr::from((Ipv6Addr::LOCALHOST, 1)),
+                datagram: Vec::new(),
+            },
+        ];
+        let mut capture = Vec::new();
+        for record in &records {
+            write_record(&mut capture, record).unwrap();
+        }
+        let mut reader = &capture[..];
+        assert_eq!(Some(records[0].clone()), read_record(&mut reader).unwrap());
+        assert_eq!(Some(records[1].clone()), read_record(&mut reader).unwrap());
+        assert_eq!(None, read_record(&mut reader).unwrap());
+        // A record cut short is an error, not the end of the capture.
+        let mut truncated = &capture[..capture.len() - 1];
+        read_record(&mut truncated).unwrap();
+        assert!(read_record(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let config = Config {
+            retransmission_timeout: Duration::from_millis(250),
+            session_expiry_timeout: Duration::from_secs(7),
+            send_window: 3,
+            receive_window: 5,
+            address_policy: AddressPolicy::Migrate,
+        };
+        let mut header = Vec::new();
+        write_config(&mut header, &config).unwrap();
+        let read = read_config(&mut &header[..]).unwrap();
+        assert_eq!(config.retransmission_timeout, read.retransmission_timeout);
+        assert_eq!(config.session_expiry_timeout, read.session_expiry_timeout);
+        assert_eq!(config.send_window, read.send_window);
+        assert_eq!(config.receive_window, read.receive_window);
+        assert_eq!(config.address_policy, read.address_policy);
+        *header.last_mut().unwrap() = 2;
+        assert!(read_config(&mut &header[..]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_reproduces_capture() {
+        let config = Config {
+            retransmission_timeout: Duration::from_millis(100),
+            session_expiry_timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let network = Network::new(
+            15,
+            Conditions {
+                drop: 0.2,
+                duplicate: 0.1,
+                reorder: 0.1,
+                latency: Duration::from_millis(10),
+            },
+        );
+        let path = std::env::temp_dir().join(format!("lrcp-capture-{}", std::process::id()));
+        let recorder =
+            Arc::new(Recorder::create(Arc::new(network.bind()), &path, &config).unwrap());
+        let server = recorder.local_addr().unwrap();
+        let mut listener = LrcpListener::from_socket(recorder.clone(), config).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(reverse_lines(stream));
+            }
+        });
+        let mut stream = LrcpStream::connect_on(Arc::new(network.bind()), server, config)
+            .await
+            .unwrap();
+        for i in 0..50 {
+            let line = format!("line {} with a / and a \\\n", i);
+            stream.write_all(line.as_bytes()).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+        let mut reversed = Vec::new();
+        stream.read_to_end(&mut reversed).await.unwrap();
+        time::sleep(config.session_expiry_timeout).await;
+        recorder.sync().unwrap();
+        let capture = read_capture(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The replay runs under the recorded config, so it retransmits on the
+        // same schedule as the recording did.
+        let replayed = replay(&capture, |stream| async {
+            reverse_lines(stream).await.unwrap();
+        })
+        .await;
+        let sent: Vec<_> = capture
+            .records
+            .iter()
+            .filter(|record| record.direction == Direction::Outbound)
+            .map(|record| (record.addr, &record.datagram))
+            .collect();
+        let resent: Vec<_> = replayed
+            .iter()
+            .map(|record| (record.addr, &record.datagram))
+            .collect();
+        assert!(sent.len() > 50);
+        assert_eq!(sent, resent);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use bytes::BytesMut;
+use rand::Rng;
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    sync::mpsc::{self, unbounded_channel, UnboundedSender},
+    time::{self, Instant},
+};
+
+use crate::{
+    metrics::Counters,
+    packet::{parse_message, Message, MAX_NUMBER, MAX_PACKET_LEN},
+    server::{handle, Channel, Config, Outbound, State},
+    socket::{self, Socket},
+};
+
+// Opens a session with a random id to the server at addr, returning the
+// application's channel once the server has acked the connect.
+#[tracing::instrument(skip(config))]
+pub(crate) async fn open(addr: &str, config: Config) -> io::Result<Channel> {
+    let addr = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = Arc::new(UdpSocket::bind(local).await?);
+    open_on(socket, addr, config).await
+}
+
+// Opens a session over the given socket, which lets tests run sessions over a
+// simulated network.
+#[tracing::instrument(skip(socket, config))]
+pub(crate) async fn open_on(
+    socket: Arc<dyn Socket>,
+    addr: SocketAddr,
+    config: Config,
+) -> io::Result<Channel> {
+    let session = rand::thread_rng().gen_range(0..=MAX_NUMBER);
+    let outbound = Outbound::spawn(socket.clone());
+    handshake(socket.as_ref(), &outbound, addr, session, config).await?;
+    tracing::info!(session, "connected");
+    let (tx, rx) = unbounded_channel();
+    let (reader_tx, reader_rx) = unbounded_channel();
+    let (writer_tx, writer_rx) = mpsc::channel(1);
+    let counters = Arc::new(Counters::new(session, addr));
+    let state = State::new(session, addr, outbound, config, counters);
+    tokio::spawn(handle(state, rx, reader_tx, writer_rx));
+    tokio::spawn(receive(socket, addr, tx));
+    Ok(Channel {
+        session,
+        addr,
+        rx: reader_rx,
+        tx: writer_tx,
+    })
+}
+
+// Sends connect until the server acks it, giving up after the session expiry
+// timeout.
+async fn handshake(
+    socket: &dyn Socket,
+    outbound: &Outbound,
+    addr: SocketAddr,
+    session: u64,
+    config: Config,
+) -> io::Result<()> {
+    let expires_at = Instant::now() + config.session_expiry_timeout;
+    let mut buf = BytesMut::with_capacity(socket::RECEIVE_BUFFER_LEN);
+    while Instant::now() < expires_at {
+        outbound.send(addr, &Message::Connect(session)).await;
+        let retransmit_at = Instant::now() + config.retransmission_timeout;
+        while let Ok(received) = time::timeout_at(
+            retransmit_at,
+            socket::recv_bytes_from(socket, &mut buf, MAX_PACKET_LEN + 1),
+        )
+        .await
+        {
+            let (packet, from) = received?;
+            if from != addr {
+                continue;
+            }
+            match parse_message(packet) {
+                Ok(Message::Ack(s, 0)) if s == session => return Ok(()),
+                Ok(Message::Close(s)) if s == session => {
+                    return Err(io::ErrorKind::ConnectionRefused.into());
+                }
+                _ => {}
+            }
+        }
+    }
+    Err(io::ErrorKind::TimedOut.into())
+}
+
+// Forwards our session's packets from the server to the session task until
+// the session closes.
+async fn receive(
+    socket: Arc<dyn Socket>,
+    addr: SocketAddr,
+    tx: UnboundedSender<(Message, SocketAddr)>,
+) {
+    let mut buf = BytesMut::with_capacity(socket::RECEIVE_BUFFER_LEN);
+    loop {
+        let received = tokio::select! {
+            received = socket::recv_bytes_from(socket.as_ref(), &mut buf, MAX_PACKET_LEN + 1) => received,
+            _ = tx.closed() => return,
+        };
+        let (packet, from) = match received {
+            Ok(received) => received,
+            Err(err) => {
+                tracing::error!(?err, "receiving packet");
+                return;
+            }
+        };
+        if from != addr || packet.len() > MAX_PACKET_LEN {
+            continue;
+        }
+        match parse_message(packet) {
+            Ok(Message::Connect(_)) => {}
+            Ok(message) => {
+                if tx.send((message, from)).is_err() {
+                    return;
+                }
+            }
+            Err(err) => tracing::debug!(%err, "dropping invalid packet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        reverse::reverse_lines,
+        stream::{LrcpListener, LrcpStream},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_against_line_reversal() {
+        // Bursts can overflow socket buffers even on loopback, so recover quickly.
+        let config = Config {
+            retransmission_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut listener = LrcpListener::bind_with("127.0.0.1:0", config)
+            .await
+            .unwrap();
+        let addr = listener.local_addr().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            reverse_lines(stream).await.unwrap();
+        });
+        let mut stream = LrcpStream::connect_with(&addr, config).await.unwrap();
+        let mut expected = Vec::new();
+        for i in 0..100 {
+            let line = format!("line {} with / and \\ in it", i);
+            stream.write_all(line.as_bytes()).await.unwrap();
+            stream.write_all(b"\n").await.unwrap();
+            expected.extend(line.bytes().rev());
+            expected.push(b'\n');
+        }
+        let mut reversed = vec![0u8; expected.len()];
+        stream.read_exact(&mut reversed).await.unwrap();
+        assert_eq!(expected, reversed);
+        // Shutting down closes the session, which the reader sees as EOF.
+        stream.shutdown().await.unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_times_out() {
+        // Nothing answers on this socket.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            retransmission_timeout: Duration::from_millis(10),
+            session_expiry_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let addr = silent.local_addr().unwrap().to_string();
+        let err = LrcpStream::connect_with(&addr, config).await.unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod capture;
+pub mod client;
+pub mod metrics;
+pub mod packet;
+pub mod reverse;
+pub mod server;
+pub mod sim;
+pub mod socket;
+pub mod stream;
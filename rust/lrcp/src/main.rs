@@ -1,10 +1,71 @@
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
+use std::{path::PathBuf, sync::Arc};
 
-pub mod packet;
-pub mod server;
+use clap::Parser;
+use lrcp::{
+    capture::Recorder, metrics::Metrics, reverse::reverse_lines, server::Config, socket::Socket,
+    stream::LrcpListener,
+};
+use tokio::net::UdpSocket;
 
-fn main() {
-    println!("Hello, world!");
+/// Line Reversal over LRCP: every line a peer sends comes back reversed.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The address to bind; fly.io only routes udp to fly-global-services.
+    #[arg(long, default_value = "0.0.0.0")]
+    host: String,
+    /// The udp port to bind.
+    #[arg(long, default_value_t = 9000)]
+    port: u16,
+    /// Record every datagram to this file, for lrcp-replay.
+    #[arg(long)]
+    record: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+    let socket: Arc<dyn Socket> =
+        Arc::new(UdpSocket::bind(format!("{}:{}", args.host, args.port)).await?);
+    let config = Config::default();
+    let socket: Arc<dyn Socket> = match args.record {
+        Some(path) => Arc::new(Recorder::create(socket, path, &config)?),
+        None => socket,
+    };
+    let mut listener = LrcpListener::from_socket(socket, config)?;
+    tracing::info!(addr = %listener.local_addr(), "listening");
+    let metrics = listener.metrics();
+    tokio::spawn(async move {
+        if let Err(err) = dump_sessions(metrics).await {
+            tracing::error!(?err, "dumping sessions");
+        }
+    });
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let session = stream.session();
+        tokio::spawn(async move {
+            if let Err(err) = reverse_lines(stream).await {
+                tracing::error!(?err, session, ?addr, "reversing lines");
+            }
+        });
+    }
+}
+
+// Logs a table of the live sessions whenever we get SIGUSR1.
+#[cfg(unix)]
+async fn dump_sessions(metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = signal(SignalKind::user_defined1())?;
+    while signals.recv().await.is_some() {
+        tracing::info!("live sessions\n{}", metrics.snapshot());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn dump_sessions(_metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    Ok(())
 }
@@ -0,0 +1,282 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use tokio::time::Instant;
+
+use crate::packet::Session;
+
+// The running counts for one session, updated by its task as it goes.
+#[derive(Debug)]
+pub(crate) struct Counters {
+    session: Session,
+    addr: Mutex<SocketAddr>,
+    bytes_in: AtomicUsize,
+    bytes_out: AtomicUsize,
+    invalid: AtomicUsize,
+    duplicates: AtomicUsize,
+    retransmits: AtomicUsize,
+    last_heard: Mutex<Instant>,
+}
+
+impl Counters {
+    pub(crate) fn new(session: Session, addr: SocketAddr) -> Self {
+        Self {
+            session,
+            addr: Mutex::new(addr),
+            bytes_in: Default::default(),
+            bytes_out: Default::default(),
+            invalid: Default::default(),
+            duplicates: Default::default(),
+            retransmits: Default::default(),
+            last_heard: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn invalid(&self) {
+        self.invalid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn retransmitted(&self) -> usize {
+        self.retransmits.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub(crate) fn moved(&self, addr: SocketAddr) {
+        *self.addr.lock().unwrap() = addr;
+    }
+
+    pub(crate) fn heard(&self) {
+        *self.last_heard.lock().unwrap() = Instant::now();
+    }
+
+    pub(crate) fn snapshot(&self) -> SessionMetrics {
+        SessionMetrics {
+            session: self.session,
+            addr: *self.addr.lock().unwrap(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            retransmits: self.retransmits.load(Ordering::Relaxed),
+            last_heard: *self.last_heard.lock().unwrap(),
+        }
+    }
+}
+
+// A point-in-time copy of one session's counters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionMetrics {
+    pub session: Session,
+    pub addr: SocketAddr,
+    // Bytes received in order from the peer.
+    pub bytes_in: usize,
+    // Payload bytes sent to the peer in data packets, counting every
+    // retransmission, so it can run ahead of what the peer has received.
+    pub bytes_out: usize,
+    // Packets from the peer's address that we could not parse or that broke
+    // the protocol.
+    pub invalid: usize,
+    // Data packets for bytes we had already received.
+    pub duplicates: usize,
+    // The number of times we've resent unacknowledged data.
+    pub retransmits: usize,
+    pub last_heard: Instant,
+}
+
+// The counters of every live session on a server. Sessions own their
+// counters, so a session's entry goes away when its task ends.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    sessions: Mutex<BTreeMap<Session, Weak<Counters>>>,
+    // Invalid packets from addresses without a live session.
+    invalid: AtomicUsize,
+}
+
+impl Metrics {
+    pub(crate) fn register(&self, session: Session, addr: SocketAddr) -> Arc<Counters> {
+        let counters = Arc::new(Counters::new(session, addr));
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session, Arc::downgrade(&counters));
+        counters
+    }
+
+    // Charges an unparseable packet to the sessions at its address.
+    pub(crate) fn invalid_from(&self, addr: SocketAddr) {
+        let mut charged = false;
+        for counters in self.live() {
+            if *counters.addr.lock().unwrap() == addr {
+                counters.invalid();
+                charged = true;
+            }
+        }
+        if !charged {
+            self.invalid.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn live(&self) -> Vec<Arc<Counters>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, counters| counters.strong_count() > 0);
+        sessions.values().filter_map(Weak::upgrade).collect()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            sessions: self.live().iter().map(|c| c.snapshot()).collect(),
+            invalid: self.invalid.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    // The live sessions, ordered by session id.
+    pub sessions: Vec<SessionMetrics>,
+    pub invalid: usize,
+}
+
+// Renders the sessions as a table for the log.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>21} {:>10} {:>10} {:>7} {:>10} {:>11} {:>10}",
+            "session", "addr", "in", "out", "invalid", "duplicates", "retransmits", "idle"
+        )?;
+        let now = Instant::now();
+        for s in &self.sessions {
+            writeln!(
+                f,
+                "{:>10} {:>21} {:>10} {:>10} {:>7} {:>10} {:>11} {:>10.1?}",
+                s.session,
+                s.addr.to_string(),
+                s.bytes_in,
+                s.bytes_out,
+                s.invalid,
+                s.duplicates,
+                s.retransmits,
+                now.saturating_duration_since(s.last_heard),
+            )?;
+        }
+        write!(
+            f,
+            "{} sessions, {} invalid packets from unknown peers",
+            self.sessions.len(),
+            self.invalid
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{io::AsyncWriteExt, net::UdpSocket, time};
+
+    use crate::{server::Config, sim::Network, socket, stream::LrcpListener};
+
+    async fn roundtrip(client: &UdpSocket, packet: &[u8]) -> Vec<u8> {
+        client.send(packet).await.unwrap();
+        let mut buf = [0u8; 1000];
+        let len = client.recv(&mut buf).await.unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let mut listener = LrcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics = listener.metrics();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(listener.local_addr()).await.unwrap();
+        roundtrip(&client, b"/connect/1/").await;
+        let (mut stream, _) = listener.accept().await.unwrap();
+        roundtrip(&client, b"/data/1/0/hello/").await;
+        roundtrip(&client, b"/data/1/0/hello/").await;
+        roundtrip(&client, b"/data/1/5/\n/").await;
+        tokio::io::AsyncWriteExt::write_all(&mut stream, b"hi")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1000];
+        client.recv(&mut buf).await.unwrap();
+        client.send(b"/data/1/").await.unwrap();
+        // The server handles packets in order, so once the connect is acked,
+        // the invalid packet has been counted.
+        roundtrip(&client, b"/connect/1/").await;
+        let snapshot = metrics.snapshot();
+        assert_eq!(1, snapshot.sessions.len());
+        let session = &snapshot.sessions[0];
+        assert_eq!(1, session.session);
+        assert_eq!(client.local_addr().unwrap(), session.addr);
+        assert_eq!(6, session.bytes_in);
+        assert_eq!(2, session.bytes_out);
+        assert_eq!(1, session.invalid);
+        assert_eq!(1, session.duplicates);
+        assert_eq!(0, snapshot.invalid);
+        let table = snapshot.to_string();
+        assert!(table.starts_with("   session"), "{}", table);
+        assert!(table.ends_with("1 sessions, 0 invalid packets from unknown peers"));
+        // Once the session closes, it drops out of the snapshot.
+        assert_eq!(
+            b"/close/1/".to_vec(),
+            roundtrip(&client, b"/close/1/").await
+        );
+        while !metrics.snapshot().sessions.is_empty() {
+            time::sleep(time::Duration::from_millis(1)).await;
+        }
+        let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        stranger
+            .send_to(b"/bogus/", listener.local_addr())
+            .await
+            .unwrap();
+        while metrics.snapshot().invalid == 0 {
+            time::sleep(time::Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bytes_out_counts_retransmissions() {
+        let network = Network::new(0, Default::default());
+        let config = Config {
+            send_window: 3,
+            ..Default::default()
+        };
+        let mut listener = LrcpListener::from_socket(Arc::new(network.bind()), config).unwrap();
+        let metrics = listener.metrics();
+        let addr = listener.local_addr();
+        let client = network.bind();
+        let mut buf = [0u8; 1000];
+        socket::send_to(&client, b"/connect/2/", addr)
+            .await
+            .unwrap();
+        socket::recv_from(&client, &mut buf).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        // The first send, then a retransmission once the clock runs on.
+        for _ in 0..2 {
+            let (len, _) = socket::recv_from(&client, &mut buf).await.unwrap();
+            assert_eq!(b"/data/2/0/hel/", &buf[..len]);
+        }
+        // The rest is held back by the window, so it hasn't been sent.
+        assert_eq!(6, metrics.snapshot().sessions[0].bytes_out);
+    }
+}
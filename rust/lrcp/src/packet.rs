@@ -1,58 +1,123 @@
-use regex::bytes::{Captures, Regex};
-use std::cmp::{max, min};
-use std::collections::VecDeque;
+use std::cmp::min;
 use std::fmt::Debug;
 use std::io::Read;
-use std::ops::Range;
-use std::str::FromStr;
+
+use bytes::{Buf, Bytes};
 
 // Protohackers doesn't specify a max session value, actually, but I
 // guess we'll start here for convenience.
 pub type Session = u64;
 
 // Numeric field values must be smaller than 2147483648.
-// TODO this should be u32, not usize, but converting the ranges is confusing
+// TODO this should be u32, not usize, but converting the offsets is confusing
 // and distracting from more important lessons.
 pub type Position = usize;
 
-#[derive(Debug, PartialEq, Eq)]
+// Packets must be smaller than 1000 bytes.
+pub const MAX_PACKET_LEN: usize = 999;
+
+// Data shares the buffer of the packet it was parsed from and unescapes its
+// payload only as it's read, so receiving data never copies it until the
+// application reads it.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Data {
     session: Session,
     position: Position,
-    source: Vec<u8>,
-    ranges: VecDeque<Range<Position>>,
-    range_cursor: Position,
+    // The escaped payload that remains to be read.
+    escaped: Bytes,
 }
 
-// TODO if we didn't want to hold onto source for the entire read, we could either
-// nibble off its front ranges as we go. Alternately, if we want to leave source
-// alone, maybe we should box up an array instead, if that's a thing you can do.
 impl Read for Data {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
-            return Ok(0);
-        }
         let mut buf_offset = 0;
-        loop {
-            let range = self.ranges.front();
-            if range.is_none() {
-                break;
+        while buf_offset < buf.len() && !self.escaped.is_empty() {
+            let run = self.run();
+            if run == 0 {
+                // Parsing guarantees an escaped byte follows the backslash.
+                buf[buf_offset] = self.escaped[1];
+                buf_offset += 1;
+                self.escaped.advance(2);
+                continue;
             }
-            let range = range.unwrap();
-            let possible = range.len();
-            let wanted = buf.len() - buf_offset;
-            let taking = min(possible, wanted);
-            let source = &self.source[range.start..range.start + taking];
-            buf[buf_offset..buf_offset + taking].copy_from_slice(source);
+            let taking = min(run, buf.len() - buf_offset);
+            buf[buf_offset..buf_offset + taking].copy_from_slice(&self.escaped[..taking]);
             buf_offset += taking;
-            if taking == range.len() {
-                self.ranges.pop_front();
-            }
+            self.escaped.advance(taking);
         }
         Ok(buf_offset)
     }
 }
 
+impl Data {
+    pub fn new(session: Session, position: Position, payload: Vec<u8>) -> Self {
+        let mut source = Vec::with_capacity(payload.len());
+        for byte in payload {
+            if is_escaped(byte) {
+                source.push(b'\\');
+            }
+            source.push(byte);
+        }
+        Data {
+            session,
+            position,
+            escaped: source.into(),
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        self.session
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    // The number of unread payload bytes, once unescaped.
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut bytes = self.escaped.iter();
+        while let Some(byte) = bytes.next() {
+            if *byte == b'\\' {
+                bytes.next();
+            }
+            len += 1;
+        }
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.escaped.is_empty()
+    }
+
+    // Skips over the next n payload bytes without reading them, or all of
+    // them if there are fewer.
+    pub fn skip(&mut self, mut n: usize) {
+        while n > 0 && !self.escaped.is_empty() {
+            let run = self.run();
+            if run == 0 {
+                self.escaped.advance(2);
+                n -= 1;
+            } else {
+                let taking = min(run, n);
+                self.escaped.advance(taking);
+                n -= taking;
+            }
+        }
+    }
+
+    // The length of the escaped payload before its next escape.
+    fn run(&self) -> usize {
+        self.escaped
+            .iter()
+            .position(|byte| *byte == b'\\')
+            .unwrap_or(self.escaped.len())
+    }
+}
+
+fn is_escaped(byte: u8) -> bool {
+    byte == b'/' || byte == b'\\'
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Message {
     Connect(Session),
@@ -61,139 +126,467 @@ pub enum Message {
     Close(Session),
 }
 
-lazy_static! {
-    static ref CONNECT: Regex = Regex::new(r"/connect/(?P<session>\d+)/").unwrap();
-    static ref CLOSE: Regex = Regex::new(r"/close/(?P<session>\d+)/").unwrap();
-    static ref DATA: Regex =
-        Regex::new(r"/data/(?P<session>\d+)/(?P<position>\d+)/(?P<data>.*)/").unwrap();
-    static ref ACK: Regex = Regex::new(r"/ack/(?P<session>\d+)/(?P<position>\d+)").unwrap();
-    static ref SLASHES: Regex = Regex::new(r"\\(?:\\|/)").unwrap();
+impl Message {
+    pub fn session(&self) -> Session {
+        match self {
+            Message::Connect(session) => *session,
+            Message::Data(data) => data.session,
+            Message::Ack(session, _) => *session,
+            Message::Close(session) => *session,
+        }
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let packet = match self {
+            Message::Connect(session) => format!("/connect/{}/", session).into_bytes(),
+            Message::Data(data) => {
+                let mut packet = format!("/data/{}/{}/", data.session, data.position).into_bytes();
+                packet.extend_from_slice(&data.escaped);
+                packet.push(b'/');
+                packet
+            }
+            Message::Ack(session, position) => {
+                format!("/ack/{}/{}/", session, position).into_bytes()
+            }
+            Message::Close(session) => format!("/close/{}/", session).into_bytes(),
+        };
+        if packet.len() > MAX_PACKET_LEN {
+            return Err(anyhow::anyhow!("packet too large: {} bytes", packet.len()));
+        }
+        Ok(packet)
+    }
+}
+
+// Splits a payload into as few data messages as will each encode to a legal
+// packet, starting at the given position.
+pub fn split_data(session: Session, position: Position, payload: &[u8]) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut start = 0;
+    while start < payload.len() {
+        let header = format!("/data/{}/{}/", session, position + start).len();
+        // Leave room for the trailing slash.
+        let mut room = MAX_PACKET_LEN - header - 1;
+        let mut end = start;
+        while end < payload.len() {
+            let width = if is_escaped(payload[end]) { 2 } else { 1 };
+            if width > room {
+                break;
+            }
+            room -= width;
+            end += 1;
+        }
+        messages.push(Message::Data(Data::new(
+            session,
+            position + start,
+            payload[start..end].to_vec(),
+        )));
+        start = end;
+    }
+    messages
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    // The packet is 1000 bytes or longer.
+    TooLong,
+    // The packet does not begin with a known message type.
+    UnknownType,
+    // The packet does not match the grammar of its message type.
+    Malformed(&'static str),
+    // A numeric field is not smaller than 2147483648.
+    NumberTooLarge,
+    // A data payload contains a slash that is not escaped.
+    UnescapedSlash,
+    // A data payload contains a backslash that escapes neither slash nor backslash.
+    InvalidEscape,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::TooLong => write!(f, "packet too long"),
+            ParseError::UnknownType => write!(f, "unknown message type"),
+            ParseError::Malformed(kind) => write!(f, "malformed {} message", kind),
+            ParseError::NumberTooLarge => write!(f, "numeric field too large"),
+            ParseError::UnescapedSlash => write!(f, "unescaped slash in data"),
+            ParseError::InvalidEscape => write!(f, "invalid escape in data"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Numeric field values must be smaller than 2147483648.
+pub const MAX_NUMBER: u64 = 2147483647;
+
+// Walks the slash-delimited fields of a packet.
+struct Fields<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fields<'a> {
+    // Returns the bytes up to the next slash, consuming the slash.
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.packet[self.offset..];
+        let len = rest.iter().position(|byte| *byte == b'/')?;
+        self.offset += len + 1;
+        Some(&rest[..len])
+    }
+
+    fn number(&mut self, kind: &'static str) -> Result<u64, ParseError> {
+        let field = self.next().ok_or(ParseError::Malformed(kind))?;
+        if field.is_empty() || !field.iter().all(u8::is_ascii_digit) {
+            return Err(ParseError::Malformed(kind));
+        }
+        let mut value: u64 = 0;
+        for byte in field {
+            value = value * 10 + u64::from(byte - b'0');
+            if value > MAX_NUMBER {
+                return Err(ParseError::NumberTooLarge);
+            }
+        }
+        Ok(value)
+    }
+
+    fn end(&self, kind: &'static str) -> Result<(), ParseError> {
+        if self.offset == self.packet.len() {
+            Ok(())
+        } else {
+            Err(ParseError::Malformed(kind))
+        }
+    }
 }
 
-fn must_pluck<T: FromStr>(caps: &Captures, name: &str) -> T
-where
-    <T as FromStr>::Err: Debug,
-{
-    let value = std::str::from_utf8(caps.name(name).unwrap().as_bytes()).unwrap();
-    value.parse::<T>().unwrap()
+fn validate_payload(escaped: &[u8]) -> Result<(), ParseError> {
+    let mut bytes = escaped.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'/' => return Err(ParseError::UnescapedSlash),
+            b'\\' => match bytes.next() {
+                Some(b'/') | Some(b'\\') => {}
+                _ => return Err(ParseError::InvalidEscape),
+            },
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
+// Parses the packet, whose buffer a data message goes on sharing.
 // TODO couldn't we use TryFrom idiomatically?
-pub fn parse_message(data: Vec<u8>) -> anyhow::Result<Message> {
-    if let Some(caps) = CONNECT.captures(&data) {
-        let session: Session = must_pluck(&caps, "session");
-        Ok(Message::Connect(session))
-    } else if let Some(caps) = DATA.captures(&data) {
-        let session: Session = must_pluck(&caps, "session");
-        let position: Position = must_pluck(&caps, "position");
-        let cap = caps.name("data").unwrap();
-        let start = cap.start();
-        let stop = cap.end();
-        let body = &data.as_slice()[start..stop];
-        let mut ranges: VecDeque<Range<Position>> = VecDeque::with_capacity(1);
-        {
-            let mut offset = 0;
-            // TODO could we use regex split instead of doing this manually and still get ranges?
-            // TODO alternately, should we like box up the slices instead of using ranges?
-            SLASHES
-                .find_iter(body)
-                .map(|m| m.start())
-                .for_each(|m_start| {
-                    if !(m_start == 0 && offset == 0) {
-                        ranges.push_back(start + offset..start + m_start);
-                    }
-                    offset = m_start + 1;
-                });
-            if let Some(range) = ranges.back() {
-                if range.end < stop {
-                    ranges.push_back(range.end + 1..stop);
-                }
-            } else {
-                ranges.push_back(start..stop);
+pub fn parse_message(packet: Bytes) -> Result<Message, ParseError> {
+    if packet.len() > MAX_PACKET_LEN {
+        return Err(ParseError::TooLong);
+    }
+    let mut fields = Fields {
+        packet: &packet,
+        offset: 0,
+    };
+    // Packets begin with a slash, so the first field is empty.
+    if fields.next() != Some(b"") {
+        return Err(ParseError::UnknownType);
+    }
+    match fields.next() {
+        Some(b"connect") => {
+            let session = fields.number("connect")?;
+            fields.end("connect")?;
+            Ok(Message::Connect(session))
+        }
+        Some(b"data") => {
+            let session = fields.number("data")?;
+            let position = fields.number("data")? as Position;
+            // The payload runs to the final slash.
+            let cursor = fields.offset;
+            if cursor >= packet.len() || packet[packet.len() - 1] != b'/' {
+                return Err(ParseError::Malformed("data"));
             }
+            let end = packet.len() - 1;
+            validate_payload(&packet[cursor..end])?;
+            Ok(Message::Data(Data {
+                session,
+                position,
+                escaped: packet.slice(cursor..end),
+            }))
         }
-        let data = Data {
-            session,
-            position,
-            source: data,
-            ranges,
-            range_cursor: 0,
-        };
-        Ok(Message::Data(data))
-    } else if let Some(caps) = ACK.captures(&data) {
-        let session: Session = must_pluck(&caps, "session");
-        let position: Position = must_pluck(&caps, "position");
-        Ok(Message::Ack(session, position))
-    } else if let Some(caps) = CLOSE.captures(&data) {
-        let session: Session = must_pluck(&caps, "session");
-        Ok(Message::Close(session))
-    } else {
-        Err(anyhow::anyhow!("invalid message"))
+        Some(b"ack") => {
+            let session = fields.number("ack")?;
+            let position = fields.number("ack")? as Position;
+            fields.end("ack")?;
+            Ok(Message::Ack(session, position))
+        }
+        Some(b"close") => {
+            let session = fields.number("close")?;
+            fields.end("close")?;
+            Ok(Message::Close(session))
+        }
+        _ => Err(ParseError::UnknownType),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
     fn test_parse_message_connect() {
-        let message = b"/connect/12345/".to_vec();
+        let message = Bytes::from_static(b"/connect/12345/");
         assert_eq!(Message::Connect(12345), parse_message(message).unwrap(),);
     }
 
     #[test]
     fn test_parse_message_data() {
-        let message = b"/data/12345/23/foobar/".to_vec();
+        let message = Bytes::from_static(b"/data/12345/23/foobar/");
         let data = Data {
             session: 12345,
             position: 23,
-            source: message.clone(),
-            ranges: VecDeque::from([15..21]),
-            range_cursor: 0,
+            escaped: Bytes::from_static(b"foobar"),
         };
         assert_eq!(Message::Data(data), parse_message(message).unwrap());
     }
 
     #[test]
     fn test_parse_message_data_with_slashes() {
-        let message = b"/data/12345/23/foo\\/bar\\\\baz/".to_vec();
+        let message = Bytes::from_static(b"/data/12345/23/foo\\/bar\\\\baz/");
         let data = Data {
             session: 12345,
             position: 23,
-            source: message.clone(),
-            ranges: VecDeque::from([15..18, 19..23, 24..28]),
-            range_cursor: 0,
+            escaped: Bytes::from_static(b"foo\\/bar\\\\baz"),
         };
         assert_eq!(Message::Data(data), parse_message(message).unwrap());
     }
 
     #[test]
     fn test_parse_message_data_with_all_slashes() {
-        let message = b"/data/12345/23/\\/\\/\\//".to_vec();
+        let message = Bytes::from_static(b"/data/12345/23/\\/\\/\\//");
         let data = Data {
             session: 12345,
             position: 23,
-            source: message.clone(),
-            ranges: VecDeque::from([16..17, 18..19, 20..21]),
-            range_cursor: 0,
+            escaped: Bytes::from_static(b"\\/\\/\\/"),
         };
         assert_eq!(Message::Data(data), parse_message(message).unwrap());
     }
 
     #[test]
     fn test_read_message_data() {
-        let message = b"/data/12345/23/foo\\/bar\\\\baz/".to_vec();
         let mut data = Data {
             session: 12345,
             position: 23,
-            source: message.clone(),
-            ranges: VecDeque::from([15..18, 19..23, 24..28]),
-            range_cursor: 0,
+            escaped: Bytes::from_static(b"foo\\/bar\\\\baz"),
         };
         let mut buf: Vec<u8> = vec![];
         let n = data.read_to_end(&mut buf).unwrap();
         assert_eq!(n, 11);
         assert_eq!(b"foo/bar\\baz".to_vec(), buf);
     }
+
+    #[test]
+    fn test_skip_data() {
+        let mut data = Data::new(1, 0, b"a/\\bc".to_vec());
+        assert_eq!(5, data.len());
+        data.skip(2);
+        assert_eq!(3, data.len());
+        let mut buf = vec![];
+        data.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"\\bc".to_vec(), buf);
+        let mut data = Data::new(1, 0, b"ab".to_vec());
+        data.skip(5);
+        assert!(data.is_empty());
+    }
+
+    fn roundtrip(message: Message) -> Message {
+        parse_message(message.encode().unwrap().into()).unwrap()
+    }
+
+    fn read_payload(message: Message) -> (Position, Vec<u8>) {
+        match message {
+            Message::Data(mut data) => {
+                let mut buf = vec![];
+                data.read_to_end(&mut buf).unwrap();
+                (data.position, buf)
+            }
+            _ => panic!("expected data"),
+        }
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        assert_eq!(Message::Connect(1), roundtrip(Message::Connect(1)));
+        assert_eq!(Message::Ack(2, 3), roundtrip(Message::Ack(2, 3)));
+        assert_eq!(Message::Close(4), roundtrip(Message::Close(4)));
+        let payload = b"foo/bar\\baz\n".to_vec();
+        let message = Message::Data(Data::new(5, 6, payload.clone()));
+        assert_eq!(
+            b"/data/5/6/foo\\/bar\\\\baz\n/".to_vec(),
+            message.encode().unwrap()
+        );
+        assert_eq!((6, payload), read_payload(roundtrip(message)));
+    }
+
+    #[test]
+    fn test_encode_refuses_large_packets() {
+        let message = Message::Data(Data::new(1, 0, vec![b'x'; 988]));
+        assert_eq!(MAX_PACKET_LEN, message.encode().unwrap().len());
+        let message = Message::Data(Data::new(1, 0, vec![b'x'; 989]));
+        assert!(message.encode().is_err());
+        let message = Message::Data(Data::new(1, 0, vec![b'/'; 495]));
+        assert!(message.encode().is_err());
+    }
+
+    #[test]
+    fn test_split_data() {
+        let payload: Vec<u8> = (0..5000).map(|i| b"ab/\\\n"[i % 5]).collect();
+        let messages = split_data(123, 7, &payload);
+        assert!(messages.len() > 1);
+        let mut position = 7;
+        let mut reassembled = vec![];
+        for message in messages {
+            assert!(message.encode().unwrap().len() <= MAX_PACKET_LEN);
+            let (at, bytes) = read_payload(roundtrip(message));
+            assert_eq!(position, at);
+            position += bytes.len();
+            reassembled.extend(bytes);
+        }
+        assert_eq!(payload, reassembled);
+        assert!(split_data(123, 7, b"").is_empty());
+    }
+
+    fn parse(packet: &[u8]) -> Result<Message, ParseError> {
+        parse_message(Bytes::copy_from_slice(packet))
+    }
+
+    #[test]
+    fn test_parse_message_data_with_leading_escape() {
+        let (_, payload) = read_payload(parse(b"/data/1/0/\\//").unwrap());
+        assert_eq!(b"/".to_vec(), payload);
+        let (_, payload) = read_payload(parse(b"/data/1/0//").unwrap());
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn test_parse_message_strictly() {
+        assert_eq!(Err(ParseError::UnknownType), parse(b""));
+        assert_eq!(Err(ParseError::UnknownType), parse(b"/nope/1/"));
+        assert_eq!(Err(ParseError::UnknownType), parse(b"x/connect/1/"));
+        assert_eq!(Err(ParseError::Malformed("connect")), parse(b"/connect/1"));
+        assert_eq!(
+            Err(ParseError::Malformed("connect")),
+            parse(b"/connect/-1/")
+        );
+        assert_eq!(
+            Err(ParseError::Malformed("connect")),
+            parse(b"/connect/1/x")
+        );
+        assert_eq!(Err(ParseError::Malformed("ack")), parse(b"/ack/1/2/extra"));
+        assert_eq!(Err(ParseError::Malformed("ack")), parse(b"/ack/1/2"));
+        assert_eq!(Err(ParseError::Malformed("close")), parse(b"/close/1/2/"));
+        assert_eq!(Err(ParseError::Malformed("data")), parse(b"/data/1/2/"));
+        assert_eq!(Err(ParseError::Malformed("data")), parse(b"/data/1/foo/"));
+        assert_eq!(Err(ParseError::TooLong), parse(&[b'/'; 1000]));
+    }
+
+    #[test]
+    fn test_parse_message_numeric_limits() {
+        assert_eq!(
+            Ok(Message::Connect(2147483647)),
+            parse(b"/connect/2147483647/")
+        );
+        assert_eq!(
+            Err(ParseError::NumberTooLarge),
+            parse(b"/connect/2147483648/")
+        );
+        assert_eq!(
+            Err(ParseError::NumberTooLarge),
+            parse(b"/connect/99999999999999999999999/")
+        );
+        assert_eq!(
+            Err(ParseError::NumberTooLarge),
+            parse(b"/ack/1/2147483648/")
+        );
+        assert_eq!(
+            Err(ParseError::NumberTooLarge),
+            parse(b"/data/1/2147483648/x/")
+        );
+    }
+
+    #[test]
+    fn test_parse_message_data_escapes() {
+        assert_eq!(Err(ParseError::UnescapedSlash), parse(b"/data/1/0/a/b/"));
+        assert_eq!(Err(ParseError::InvalidEscape), parse(b"/data/1/0/a\\b/"));
+        // The final slash is escaped, so the packet is unterminated.
+        assert_eq!(Err(ParseError::InvalidEscape), parse(b"/data/1/0/a\\/"));
+    }
+
+    // Payloads heavy in the bytes that need escaping.
+    fn payload(max_len: usize) -> impl Strategy<Value = Vec<u8>> {
+        let byte = any::<u8>().prop_map(|byte| match byte {
+            0..=31 => b'/',
+            32..=63 => b'\\',
+            byte => byte,
+        });
+        prop::collection::vec(byte, 0..max_len)
+    }
+
+    // Reads everything from the reader, taking each read's buffer size in
+    // turn from sizes.
+    fn read_in_pieces(reader: &mut impl Read, sizes: &[usize]) -> Vec<u8> {
+        let mut read = Vec::new();
+        for size in sizes.iter().cycle() {
+            let mut buf = vec![0u8; *size];
+            let len = reader.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            read.extend_from_slice(&buf[..len]);
+        }
+        read
+    }
+
+    proptest! {
+        #[test]
+        fn prop_data_reads_back_in_pieces(
+            session in 0..=MAX_NUMBER,
+            position in 0..=MAX_NUMBER as usize,
+            payload in payload(400),
+            sizes in prop::collection::vec(1..64usize, 1..8),
+        ) {
+            let packet = Message::Data(Data::new(session, position, payload.clone()))
+                .encode()
+                .unwrap();
+            let mut data = match parse_message(packet.into()) {
+                Ok(Message::Data(data)) => data,
+                other => panic!("expected data, got {:?}", other),
+            };
+            prop_assert_eq!(session, data.session());
+            prop_assert_eq!(position, data.position());
+            prop_assert_eq!(payload, read_in_pieces(&mut data, &sizes));
+        }
+
+        #[test]
+        fn prop_split_data_reads_back(
+            payload in payload(5000),
+            sizes in prop::collection::vec(1..2000usize, 1..8),
+        ) {
+            let mut read = Vec::new();
+            for message in split_data(1, 0, &payload) {
+                let packet = message.encode().unwrap();
+                prop_assert!(packet.len() <= MAX_PACKET_LEN);
+                match parse_message(packet.into()) {
+                    Ok(Message::Data(mut data)) => {
+                        prop_assert_eq!(read.len(), data.position());
+                        read.extend(read_in_pieces(&mut data, &sizes));
+                    }
+                    other => panic!("expected data, got {:?}", other),
+                }
+            }
+            prop_assert_eq!(payload, read);
+        }
+
+        #[test]
+        fn prop_parse_never_panics(packet in prop::collection::vec(any::<u8>(), 0..1100)) {
+            let _ = parse_message(packet.into());
+        }
+    }
 }
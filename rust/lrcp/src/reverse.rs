@@ -0,0 +1,46 @@
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+// Reads newline-terminated lines from the stream and writes each one back
+// reversed, byte for byte. A final line without a newline is never answered.
+pub async fn reverse_lines<S>(stream: S) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.pop() != Some(b'\n') {
+            return Ok(());
+        }
+        line.reverse();
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reverse_lines() {
+        let (client, server) = duplex(64);
+        tokio::spawn(reverse_lines(server));
+        let (mut reader, mut writer) = tokio::io::split(client);
+        writer
+            .write_all(b"hello\n\nfoo/bar\\baz\nunterminated")
+            .await
+            .unwrap();
+        writer.shutdown().await.unwrap();
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(b"olleh\n\nzab\\rab/oof\n".to_vec(), out);
+    }
+}
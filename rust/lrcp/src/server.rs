@@ -1,45 +1,848 @@
-use std::{collections::BTreeMap, net::SocketAddr};
+use std::{collections::BTreeMap, io, net::SocketAddr, sync::Arc, time::Duration};
 
+use bytes::BytesMut;
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    sync::mpsc::{
+        self, error::TrySendError, unbounded_channel, UnboundedReceiver, UnboundedSender,
+    },
+    time::{self, Instant},
 };
 
-use crate::packet::{parse_message, Message, Session};
+use crate::{
+    metrics::{Counters, Metrics},
+    packet::{parse_message, split_data, Data, Message, Position, Session, MAX_PACKET_LEN},
+    socket::{self, Socket},
+};
+
+// The number of datagrams that may wait for the socket before senders must
+// wait for room.
+const SEND_QUEUE_LEN: usize = 256;
+
+// The application's view of a session: data received in order from the peer
+// arrive on rx, still escaped until they're read, and bytes written to tx are
+// sent to the peer.
+#[derive(Debug)]
+pub struct Channel {
+    pub session: Session,
+    pub addr: SocketAddr,
+    pub rx: UnboundedReceiver<Data>,
+    // Bounded, so writers wait while the session's send window is full.
+    pub tx: mpsc::Sender<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    // How long to wait for an ack before resending unacknowledged data.
+    pub retransmission_timeout: Duration,
+    // How long to wait to hear anything from a peer before closing its session.
+    pub session_expiry_timeout: Duration,
+    // The most bytes we'll have sent that the peer hasn't acknowledged. Once
+    // the application has written this much more than has been acked, its
+    // writes wait.
+    pub send_window: usize,
+    // The most bytes received out of order that we'll hold while waiting for
+    // the gap before them to fill. Beyond this, we drop them and let the peer
+    // retransmit.
+    pub receive_window: usize,
+    // What to do with packets for a session from an address other than its
+    // peer's.
+    pub address_policy: AddressPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressPolicy {
+    // Drop them, so nobody who guesses a session id can inject data into it.
+    #[default]
+    Reject,
+    // Take them as the peer having moved, as when a nat rebinds its port,
+    // and send to the new address from then on.
+    Migrate,
+}
 
-pub async fn listen(addr: &str) -> anyhow::Result<()> {
-    let mut sessions: BTreeMap<Session, UnboundedSender<Message>> = Default::default();
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            retransmission_timeout: Duration::from_secs(3),
+            session_expiry_timeout: Duration::from_secs(60),
+            send_window: 64 * 1024,
+            receive_window: 64 * 1024,
+            address_policy: Default::default(),
+        }
+    }
+}
+
+pub async fn listen(
+    addr: &str,
+    config: Config,
+    metrics: Arc<Metrics>,
+    channels: UnboundedSender<Channel>,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    serve(Arc::new(socket), config, metrics, channels).await
+}
+
+#[tracing::instrument(skip(socket, metrics, channels))]
+pub async fn serve(
+    socket: Arc<dyn Socket>,
+    config: Config,
+    metrics: Arc<Metrics>,
+    channels: UnboundedSender<Channel>,
+) -> anyhow::Result<()> {
+    let outbound = Outbound::spawn(socket.clone());
+    let mut sessions: BTreeMap<Session, Route> = Default::default();
+    let mut buf = BytesMut::with_capacity(socket::RECEIVE_BUFFER_LEN);
     loop {
-        let socket = UdpSocket::bind(addr).await?;
-        let mut buf: Vec<u8> = Vec::with_capacity(1000);
-        let (len, addr) = socket.recv_from(&mut buf).await?;
-        if len == 1000 {
+        // One byte more than the largest legal packet, so we can tell when a
+        // packet was too large and truncated.
+        let received = socket::recv_bytes_from(socket.as_ref(), &mut buf, MAX_PACKET_LEN + 1);
+        let (packet, addr) = match received.await {
+            Ok(received) => received,
+            // Some platforms report an earlier send's icmp error here, which
+            // says nothing about the socket itself.
+            Err(err) if is_transient(&err) => {
+                tracing::debug!(?err, "ignoring receive error");
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if packet.len() > MAX_PACKET_LEN {
             // Illegal packet, too large.
             continue;
         }
-        let message = parse_message(buf);
-        if message.is_err() {
-            // Illegal message.
-            continue;
-        }
-        let message = message.unwrap();
-        match message {
-            Message::Connect(session) => {
-                let tx = if let Some(tx) = sessions.get(&session) {
-                    (*tx).clone()
-                } else {
-                    let (tx, rx) = unbounded_channel();
-                    sessions.insert(session, tx.clone());
-                    tx
-                };
-                tx.send(message);
+        let message = match parse_message(packet) {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::debug!(%err, ?addr, "dropping invalid packet");
+                metrics.invalid_from(addr);
                 continue;
             }
-            Message::Close(session) => {}
-            Message::Data(data) => {}
-            Message::Ack(session, position) => {}
+        };
+        let session = message.session();
+        if sessions
+            .get(&session)
+            .is_some_and(|route| route.tx.is_closed())
+        {
+            // The session task has stopped, so the session is closed.
+            sessions.remove(&session);
+        }
+        let message = if let Some(route) = sessions.get_mut(&session) {
+            if route.addr != addr {
+                match config.address_policy {
+                    AddressPolicy::Reject => {
+                        tracing::info!(
+                            session,
+                            ?addr,
+                            peer = ?route.addr,
+                            "rejecting packet from another address"
+                        );
+                        metrics.invalid_from(addr);
+                        continue;
+                    }
+                    AddressPolicy::Migrate => {
+                        tracing::info!(
+                            session,
+                            from = ?route.addr,
+                            to = ?addr,
+                            "migrating session"
+                        );
+                        route.addr = addr;
+                    }
+                }
+            }
+            match route.tx.send((message, addr)) {
+                Ok(()) => continue,
+                Err(err) => {
+                    sessions.remove(&session);
+                    err.0 .0
+                }
+            }
+        } else {
+            message
+        };
+        if let Message::Connect(session) = message {
+            let (tx, rx) = unbounded_channel();
+            let (reader_tx, reader_rx) = unbounded_channel();
+            let (writer_tx, writer_rx) = mpsc::channel(1);
+            let counters = metrics.register(session, addr);
+            let state = State::new(session, addr, outbound.clone(), config, counters);
+            tokio::spawn(handle(state, rx, reader_tx, writer_rx));
+            tx.send((message, addr)).expect("send to new session");
+            sessions.insert(session, Route { addr, tx });
+            let channel = Channel {
+                session,
+                addr,
+                rx: reader_rx,
+                tx: writer_tx,
+            };
+            if channels.send(channel).is_err() {
+                tracing::warn!(session, "no application is accepting sessions");
+            }
+        } else {
+            tracing::info!(session, "closing unknown session");
+            // Waiting for room in the queue would stall every session's
+            // receipts, and the peer will ask again if we drop this.
+            outbound.try_send(addr, &Message::Close(session));
+        }
+    }
+}
+
+// Where the serve loop sends a session's packets, and the address they must
+// come from.
+#[derive(Debug)]
+struct Route {
+    addr: SocketAddr,
+    tx: UnboundedSender<(Message, SocketAddr)>,
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+    )
+}
+
+// The queue of datagrams bound for a socket, shared by every session on it.
+// A single task drains it into the socket, and since the queue is bounded and
+// its waiting senders are served in turn, a session with a lot to send waits
+// its turn rather than starving the others.
+#[derive(Clone, Debug)]
+pub(crate) struct Outbound {
+    tx: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+}
+
+impl Outbound {
+    pub(crate) fn spawn(socket: Arc<dyn Socket>) -> Self {
+        let (tx, mut rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(SEND_QUEUE_LEN);
+        tokio::spawn(async move {
+            while let Some((packet, addr)) = rx.recv().await {
+                if let Err(err) = socket::send_to(socket.as_ref(), &packet, addr).await {
+                    tracing::error!(?err, ?addr, "sending packet");
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    // Queues the message, waiting for room if the queue is full.
+    pub(crate) async fn send(&self, addr: SocketAddr, message: &Message) {
+        let packet = message.encode().expect("encode outgoing message");
+        if self.tx.send((packet, addr)).await.is_err() {
+            tracing::error!(?addr, "send queue closed");
+        }
+    }
+
+    // Queues the message unless the queue is full.
+    pub(crate) fn try_send(&self, addr: SocketAddr, message: &Message) {
+        let packet = message.encode().expect("encode outgoing message");
+        match self.tx.try_send((packet, addr)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => tracing::warn!(?addr, "send queue full, dropping packet"),
+            Err(TrySendError::Closed(_)) => tracing::error!(?addr, "send queue closed"),
+        }
+    }
+}
+
+// The state of one end of a session, whether we accepted it or opened it.
+#[derive(Debug)]
+pub(crate) struct State {
+    session: Session,
+    addr: SocketAddr,
+    outbound: Outbound,
+    config: Config,
+    // The number of contiguous bytes we have received from the peer.
+    received: Position,
+    // Data received beyond a gap, by position, and their total length.
+    out_of_order: BTreeMap<Position, Data>,
+    buffered: usize,
+    // The number of bytes we have sent to the peer.
+    sent: Position,
+    // The largest number of bytes the peer has acknowledged.
+    acknowledged: Position,
+    // The bytes the application has written from acknowledged onward,
+    // retained for retransmission or waiting for room in the send window.
+    unacked: Vec<u8>,
+    // When we next resend unacknowledged data, if there is any.
+    retransmit_at: Option<Instant>,
+    counters: Arc<Counters>,
+    // When we give up on a peer we haven't heard from.
+    expires_at: Instant,
+}
+
+impl State {
+    pub(crate) fn new(
+        session: Session,
+        addr: SocketAddr,
+        outbound: Outbound,
+        config: Config,
+        counters: Arc<Counters>,
+    ) -> Self {
+        Self {
+            session,
+            addr,
+            outbound,
+            config,
+            received: 0,
+            out_of_order: BTreeMap::new(),
+            buffered: 0,
+            sent: 0,
+            acknowledged: 0,
+            unacked: Vec::new(),
+            retransmit_at: None,
+            counters,
+            expires_at: Instant::now() + config.session_expiry_timeout,
+        }
+    }
+
+    fn heard(&mut self, from: SocketAddr) {
+        if from != self.addr {
+            tracing::info!(from = %self.addr, to = %from, "peer moved");
+            self.addr = from;
+            self.counters.moved(from);
+        }
+        self.counters.heard();
+        self.expires_at = Instant::now() + self.config.session_expiry_timeout;
+    }
+
+    async fn retransmit(&mut self) {
+        let retransmits = self.counters.retransmitted();
+        tracing::debug!(
+            acknowledged = self.acknowledged,
+            sent = self.sent,
+            retransmits,
+            "retransmitting"
+        );
+        self.send_data_from(self.acknowledged).await;
+        self.retransmit_at = Some(Instant::now() + self.config.retransmission_timeout);
+    }
+
+    async fn send_ack(&self) {
+        let ack = Message::Ack(self.session, self.received);
+        self.outbound.send(self.addr, &ack).await;
+    }
+
+    async fn send_close(&self) {
+        self.outbound
+            .send(self.addr, &Message::Close(self.session))
+            .await;
+    }
+
+    // Sends the unacknowledged bytes from the given position up to sent.
+    async fn send_data_from(&self, position: Position) {
+        let bytes = &self.unacked[position - self.acknowledged..self.sent - self.acknowledged];
+        self.counters.sent(bytes.len());
+        for data in split_data(self.session, position, bytes) {
+            self.outbound.send(self.addr, &data).await;
+        }
+    }
+
+    // Sends whatever the application has written that fits in the window.
+    async fn send_more(&mut self) {
+        let position = self.sent;
+        let limit = self.acknowledged + self.config.send_window;
+        self.sent = limit
+            .min(self.acknowledged + self.unacked.len())
+            .max(position);
+        if self.sent == position {
+            return;
+        }
+        self.send_data_from(position).await;
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(Instant::now() + self.config.retransmission_timeout);
+        }
+    }
+
+    // Whether the application may write more without overfilling the window.
+    fn may_write(&self) -> bool {
+        self.unacked.len() < self.config.send_window
+    }
+
+    async fn write(&mut self, bytes: Vec<u8>) {
+        self.unacked.extend_from_slice(&bytes);
+        self.send_more().await;
+    }
+
+    // Takes in a data packet, returning any data that are now in order.
+    fn receive(&mut self, mut data: Data) -> Vec<Data> {
+        let position = data.position();
+        let len = data.len();
+        if position < self.received {
+            let skip = self.received - position;
+            if skip >= len {
+                self.counters.duplicate();
+                return Vec::new();
+            }
+            // A retransmission that overlaps what we have, so we take only
+            // the part that's new.
+            data.skip(skip);
+        } else if position > self.received {
+            if self.out_of_order.contains_key(&position) {
+                self.counters.duplicate();
+            } else if self.buffered + len <= self.config.receive_window {
+                self.buffered += len;
+                self.out_of_order.insert(position, data);
+            }
+            return Vec::new();
+        }
+        let mut received = 0;
+        let mut in_order = Vec::new();
+        if !data.is_empty() {
+            received += data.len();
+            in_order.push(data);
+        }
+        self.received += received;
+        // The packet may have filled a gap before data we already hold.
+        while let Some(entry) = self.out_of_order.first_entry() {
+            if *entry.key() > self.received {
+                break;
+            }
+            let held_at = *entry.key();
+            let mut held = entry.remove();
+            let held_len = held.len();
+            self.buffered -= held_len;
+            let skip = self.received - held_at;
+            if skip < held_len {
+                held.skip(skip);
+                self.received += held_len - skip;
+                received += held_len - skip;
+                in_order.push(held);
+            }
+        }
+        self.counters.received(received);
+        in_order
+    }
+
+    // Returns false if the peer has misbehaved and the session must close.
+    fn acknowledge(&mut self, length: Position) -> bool {
+        if length <= self.acknowledged {
+            // A duplicate or stale ack, probably reordered.
+            return true;
+        }
+        if length > self.sent {
+            tracing::warn!(length, sent = self.sent, "peer acked unsent data");
+            self.counters.invalid();
+            return false;
+        }
+        self.unacked.drain(..length - self.acknowledged);
+        self.acknowledged = length;
+        // The rest may well be in flight, so rather than resend it at once,
+        // which floods the peer when many packets are outstanding, we give it
+        // the full timeout to arrive.
+        if length < self.sent {
+            self.retransmit_at = Some(Instant::now() + self.config.retransmission_timeout);
+        } else {
+            self.retransmit_at = None;
+        }
+        true
+    }
+}
+
+// Runs a session until it closes or expires. Once the application stops
+// writing, we close the session as soon as the peer has acked everything.
+#[tracing::instrument(skip_all, fields(session = state.session, addr = %state.addr))]
+pub(crate) async fn handle(
+    mut state: State,
+    mut rx: UnboundedReceiver<(Message, SocketAddr)>,
+    reader_tx: UnboundedSender<Data>,
+    mut writer_rx: mpsc::Receiver<Vec<u8>>,
+) {
+    let mut writing = true;
+    loop {
+        tokio::select! {
+            received = rx.recv() => {
+                let message = received.map(|(message, from)| {
+                    state.heard(from);
+                    message
+                });
+                match message {
+                    Some(Message::Connect(_)) => {
+                        state.send_ack().await;
+                    }
+                    Some(Message::Data(data)) => {
+                        for data in state.receive(data) {
+                            if reader_tx.send(data).is_err() {
+                                tracing::debug!("application is no longer reading");
+                                break;
+                            }
+                        }
+                        state.send_ack().await;
+                    }
+                    Some(Message::Ack(_, length)) => {
+                        if !state.acknowledge(length)
+                            || (!writing && state.unacked.is_empty())
+                        {
+                            rx.close();
+                            state.send_close().await;
+                            break;
+                        }
+                        // The ack may have opened the window.
+                        state.send_more().await;
+                    }
+                    Some(Message::Close(_)) => {
+                        // Stop accepting messages before we reply, so the
+                        // peer can't race its next packet into a dead session.
+                        rx.close();
+                        state.send_close().await;
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            bytes = writer_rx.recv(), if writing && state.may_write() => {
+                match bytes {
+                    Some(bytes) => state.write(bytes).await,
+                    None => {
+                        writing = false;
+                        if state.unacked.is_empty() {
+                            rx.close();
+                            state.send_close().await;
+                            break;
+                        }
+                    }
+                }
+            }
+            _ = time::sleep_until(state.retransmit_at.unwrap_or(state.expires_at)),
+                if state.retransmit_at.is_some() => {
+                state.retransmit().await;
+            }
+            _ = time::sleep_until(state.expires_at) => {
+                tracing::info!("session expired");
+                rx.close();
+                break;
+            }
         }
     }
+    let metrics = state.counters.snapshot();
+    tracing::info!(
+        bytes_in = metrics.bytes_in,
+        bytes_out = metrics.bytes_out,
+        invalid = metrics.invalid,
+        duplicates = metrics.duplicates,
+        retransmits = metrics.retransmits,
+        "session closed"
+    );
 }
 
-async fn handle(addr: SocketAddr, rx: UnboundedReceiver<Message>) {}
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::sim::Network;
+
+    use super::*;
+
+    async fn start() -> (UdpSocket, UnboundedReceiver<Channel>) {
+        start_with(Default::default()).await
+    }
+
+    async fn start_with(config: Config) -> (UdpSocket, UnboundedReceiver<Channel>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (channels_tx, channels_rx) = unbounded_channel();
+        tokio::spawn(serve(
+            Arc::new(socket),
+            config,
+            Default::default(),
+            channels_tx,
+        ));
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        (client, channels_rx)
+    }
+
+    // Reads len bytes of the session's data, however they were split.
+    async fn read(channel: &mut Channel, len: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while bytes.len() < len {
+            let mut data = channel.rx.recv().await.unwrap();
+            data.read_to_end(&mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    async fn roundtrip(client: &UdpSocket, packet: &[u8]) -> Vec<u8> {
+        client.send(packet).await.unwrap();
+        recv(client).await
+    }
+
+    async fn recv(client: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 1000];
+        let len = client.recv(&mut buf).await.unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_receive() {
+        let (client, mut channels) = start().await;
+        assert_eq!(
+            b"/ack/1/0/".to_vec(),
+            roundtrip(&client, b"/connect/1/").await
+        );
+        assert_eq!(
+            b"/ack/1/0/".to_vec(),
+            roundtrip(&client, b"/connect/1/").await
+        );
+        let mut channel = channels.recv().await.unwrap();
+        assert_eq!(1, channel.session);
+        assert_eq!(
+            b"/ack/1/5/".to_vec(),
+            roundtrip(&client, b"/data/1/0/a\\/b\\\\c/").await
+        );
+        // Data beyond what we've received is answered with a duplicate ack.
+        assert_eq!(
+            b"/ack/1/5/".to_vec(),
+            roundtrip(&client, b"/data/1/9/x/").await
+        );
+        assert_eq!(
+            b"/ack/1/6/".to_vec(),
+            roundtrip(&client, b"/data/1/5/\n/").await
+        );
+        assert_eq!(b"a/b\\c".to_vec(), read(&mut channel, 5).await);
+        assert_eq!(b"\n".to_vec(), read(&mut channel, 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_and_acknowledge() {
+        let (client, mut channels) = start().await;
+        roundtrip(&client, b"/connect/2/").await;
+        let channel = channels.recv().await.unwrap();
+        channel.tx.send(b"c\\b/a".to_vec()).await.unwrap();
+        assert_eq!(b"/data/2/0/c\\\\b\\/a/".to_vec(), recv(&client).await);
+        client.send(b"/ack/2/2/").await.unwrap();
+        client.send(b"/ack/2/5/").await.unwrap();
+        // Acking more than was sent is a protocol violation.
+        assert_eq!(
+            b"/close/2/".to_vec(),
+            roundtrip(&client, b"/ack/2/6/").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (client, _channels) = start().await;
+        assert_eq!(
+            b"/close/3/".to_vec(),
+            roundtrip(&client, b"/data/3/0/x/").await
+        );
+        assert_eq!(
+            b"/close/3/".to_vec(),
+            roundtrip(&client, b"/ack/3/0/").await
+        );
+        roundtrip(&client, b"/connect/3/").await;
+        assert_eq!(
+            b"/close/3/".to_vec(),
+            roundtrip(&client, b"/close/3/").await
+        );
+        assert_eq!(
+            b"/close/3/".to_vec(),
+            roundtrip(&client, b"/data/3/0/x/").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retransmit() {
+        let config = Config {
+            retransmission_timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let (client, mut channels) = start_with(config).await;
+        roundtrip(&client, b"/connect/4/").await;
+        let channel = channels.recv().await.unwrap();
+        channel.tx.send(b"hello".to_vec()).await.unwrap();
+        assert_eq!(b"/data/4/0/hello/".to_vec(), recv(&client).await);
+        assert_eq!(b"/data/4/0/hello/".to_vec(), recv(&client).await);
+        // After a partial ack, only the remainder is retransmitted.
+        assert_eq!(
+            b"/data/4/2/llo/".to_vec(),
+            roundtrip(&client, b"/ack/4/2/").await
+        );
+        assert_eq!(b"/data/4/2/llo/".to_vec(), recv(&client).await);
+        client.send(b"/ack/4/5/").await.unwrap();
+        let mut buf = [0u8; 1000];
+        let quiet = time::timeout(Duration::from_millis(60), client.recv(&mut buf)).await;
+        assert!(quiet.is_err(), "no retransmits once everything is acked");
+    }
+
+    #[tokio::test]
+    async fn test_send_window() {
+        let config = Config {
+            send_window: 4,
+            ..Default::default()
+        };
+        let (client, mut channels) = start_with(config).await;
+        roundtrip(&client, b"/connect/6/").await;
+        let channel = channels.recv().await.unwrap();
+        channel.tx.send(b"abcdefghij".to_vec()).await.unwrap();
+        assert_eq!(b"/data/6/0/abcd/".to_vec(), recv(&client).await);
+        // Each ack opens the window by as much as it acknowledges.
+        assert_eq!(
+            b"/data/6/4/ef/".to_vec(),
+            roundtrip(&client, b"/ack/6/2/").await
+        );
+        assert_eq!(
+            b"/data/6/6/ghij/".to_vec(),
+            roundtrip(&client, b"/ack/6/6/").await
+        );
+        channel.tx.send(b"klmnop".to_vec()).await.unwrap();
+        assert_eq!(
+            b"/data/6/10/klmn/".to_vec(),
+            roundtrip(&client, b"/ack/6/10/").await
+        );
+        // Acking bytes the window has held back is a protocol violation.
+        assert_eq!(
+            b"/close/6/".to_vec(),
+            roundtrip(&client, b"/ack/6/16/").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_data_is_held() {
+        let config = Config {
+            receive_window: 4,
+            ..Default::default()
+        };
+        let (client, mut channels) = start_with(config).await;
+        roundtrip(&client, b"/connect/7/").await;
+        let mut channel = channels.recv().await.unwrap();
+        assert_eq!(
+            b"/ack/7/0/".to_vec(),
+            roundtrip(&client, b"/data/7/2/cd/").await
+        );
+        // This would hold more than the receive window, so it's dropped.
+        assert_eq!(
+            b"/ack/7/0/".to_vec(),
+            roundtrip(&client, b"/data/7/4/efg/").await
+        );
+        assert_eq!(
+            b"/ack/7/4/".to_vec(),
+            roundtrip(&client, b"/data/7/0/ab/").await
+        );
+        assert_eq!(b"abcd".to_vec(), read(&mut channel, 4).await);
+        assert_eq!(
+            b"/ack/7/7/".to_vec(),
+            roundtrip(&client, b"/data/7/4/efg/").await
+        );
+        assert_eq!(b"efg".to_vec(), read(&mut channel, 3).await);
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_data_is_accepted() {
+        let (client, mut channels) = start().await;
+        roundtrip(&client, b"/connect/10/").await;
+        let mut channel = channels.recv().await.unwrap();
+        assert_eq!(
+            b"/ack/10/3/".to_vec(),
+            roundtrip(&client, b"/data/10/0/abc/").await
+        );
+        // Only the bytes past what we've received are new.
+        assert_eq!(
+            b"/ack/10/5/".to_vec(),
+            roundtrip(&client, b"/data/10/1/bcde/").await
+        );
+        // Entirely old data changes nothing.
+        assert_eq!(
+            b"/ack/10/5/".to_vec(),
+            roundtrip(&client, b"/data/10/2/cde/").await
+        );
+        assert_eq!(b"abc".to_vec(), read(&mut channel, 3).await);
+        assert_eq!(b"de".to_vec(), read(&mut channel, 2).await);
+    }
+
+    #[tokio::test]
+    async fn test_reject_other_addresses() {
+        let (client, mut channels) = start().await;
+        roundtrip(&client, b"/connect/8/").await;
+        let mut channel = channels.recv().await.unwrap();
+        let intruder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        intruder.connect(client.peer_addr().unwrap()).await.unwrap();
+        intruder.send(b"/data/8/0/evil/").await.unwrap();
+        intruder.send(b"/close/8/").await.unwrap();
+        let mut buf = [0u8; 1000];
+        let quiet = time::timeout(Duration::from_millis(50), intruder.recv(&mut buf)).await;
+        assert!(quiet.is_err(), "the intruder hears nothing");
+        // The session carries on with its peer, untouched.
+        assert_eq!(
+            b"/ack/8/4/".to_vec(),
+            roundtrip(&client, b"/data/8/0/good/").await
+        );
+        assert_eq!(b"good".to_vec(), read(&mut channel, 4).await);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to_new_address() {
+        let config = Config {
+            address_policy: AddressPolicy::Migrate,
+            ..Default::default()
+        };
+        let (client, mut channels) = start_with(config).await;
+        roundtrip(&client, b"/connect/9/").await;
+        let mut channel = channels.recv().await.unwrap();
+        assert_eq!(
+            b"/ack/9/2/".to_vec(),
+            roundtrip(&client, b"/data/9/0/ab/").await
+        );
+        // The peer reappears at a new address, and the session follows it.
+        let moved = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        moved.connect(client.peer_addr().unwrap()).await.unwrap();
+        assert_eq!(
+            b"/ack/9/4/".to_vec(),
+            roundtrip(&moved, b"/data/9/2/cd/").await
+        );
+        channel.tx.send(b"ef".to_vec()).await.unwrap();
+        assert_eq!(b"/data/9/0/ef/".to_vec(), recv(&moved).await);
+        assert_eq!(b"ab".to_vec(), read(&mut channel, 2).await);
+        assert_eq!(b"cd".to_vec(), read(&mut channel, 2).await);
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let config = Config {
+            session_expiry_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (client, mut channels) = start_with(config).await;
+        roundtrip(&client, b"/connect/5/").await;
+        let mut channel = channels.recv().await.unwrap();
+        assert_eq!(None, channel.rx.recv().await);
+        assert_eq!(
+            b"/close/5/".to_vec(),
+            roundtrip(&client, b"/data/5/0/x/").await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chatty_session_does_not_starve_others() {
+        let network = Network::new(0, Default::default());
+        let server = Arc::new(network.bind());
+        let addr = server.local_addr().unwrap();
+        let (channels_tx, mut channels) = unbounded_channel();
+        // A window far bigger than the send queue, so the chatty session
+        // really can fill it.
+        let config = Config {
+            send_window: 1 << 20,
+            ..Default::default()
+        };
+        tokio::spawn(serve(server, config, Default::default(), channels_tx));
+        let client = network.bind();
+        let mut buf = [0u8; 1000];
+        for connect in [&b"/connect/1/"[..], b"/connect/2/"] {
+            socket::send_to(&client, connect, addr).await.unwrap();
+            socket::recv_from(&client, &mut buf).await.unwrap();
+        }
+        let chatty = channels.recv().await.unwrap();
+        let quiet = channels.recv().await.unwrap();
+        chatty.tx.send(vec![b'x'; 1 << 20]).await.unwrap();
+        quiet.tx.send(b"hi".to_vec()).await.unwrap();
+        let mut chatter = 0;
+        loop {
+            let (len, _) = socket::recv_from(&client, &mut buf).await.unwrap();
+            if &buf[..len] == b"/data/2/0/hi/" {
+                break;
+            }
+            chatter += 1;
+        }
+        // The quiet session's data is queued behind at most a queue's worth
+        // of the chatty session's, not behind all of it.
+        assert!(chatter <= SEND_QUEUE_LEN + 1, "{} packets first", chatter);
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    io::ReadBuf,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{self, Instant},
+};
+
+use crate::socket::Socket;
+
+// How badly the simulated network treats each datagram.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    // The probability a datagram is lost.
+    pub drop: f64,
+    // The probability a datagram is delivered twice.
+    pub duplicate: f64,
+    // The probability a datagram is held back for up to another latency,
+    // letting later datagrams overtake it.
+    pub reorder: f64,
+    // How long datagrams take to arrive. Datagrams that aren't held back
+    // arrive in the order they were sent.
+    pub latency: Duration,
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Debug)]
+struct Port {
+    // Delivers datagrams in order once their time comes.
+    link: UnboundedSender<(Instant, Datagram)>,
+    // Delivers datagrams immediately.
+    inbox: UnboundedSender<Datagram>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    rng: StdRng,
+    conditions: Conditions,
+    ports: HashMap<SocketAddr, Port>,
+    next_port: u16,
+}
+
+// An in-process datagram network whose losses, duplicates, and reorderings
+// are drawn from a seeded rng, so failing runs can be repeated. It runs on
+// tokio's clock, so tests can pause time and skip the waiting.
+#[derive(Clone, Debug)]
+pub struct Network {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Network {
+    pub fn new(seed: u64, conditions: Conditions) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                rng: StdRng::seed_from_u64(seed),
+                conditions,
+                ports: HashMap::new(),
+                next_port: 1,
+            })),
+        }
+    }
+
+    // Binds a socket to a fresh address on the network. This must be called
+    // within a tokio runtime.
+    pub fn bind(&self) -> SimSocket {
+        let (inbox, rx) = unbounded_channel();
+        let (link, mut link_rx) = unbounded_channel::<(Instant, Datagram)>();
+        let delivering = inbox.clone();
+        tokio::spawn(async move {
+            while let Some((arrives_at, datagram)) = link_rx.recv().await {
+                time::sleep_until(arrives_at).await;
+                if delivering.send(datagram).is_err() {
+                    return;
+                }
+            }
+        });
+        let mut inner = self.inner.lock().unwrap();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, inner.next_port));
+        inner.next_port += 1;
+        inner.ports.insert(addr, Port { link, inbox });
+        SimSocket {
+            addr,
+            network: self.clone(),
+            rx: Mutex::new(rx),
+        }
+    }
+
+    fn transmit(&self, from: SocketAddr, to: SocketAddr, buf: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let Conditions {
+            drop,
+            duplicate,
+            reorder,
+            latency,
+        } = inner.conditions;
+        if inner.rng.gen_bool(drop) {
+            return;
+        }
+        let copies = if inner.rng.gen_bool(duplicate) { 2 } else { 1 };
+        for _ in 0..copies {
+            let held = if inner.rng.gen_bool(reorder) {
+                Some(latency.mul_f64(inner.rng.gen()))
+            } else {
+                None
+            };
+            // Like udp, sending to nobody silently goes nowhere.
+            let port = match inner.ports.get(&to) {
+                Some(port) => port,
+                None => return,
+            };
+            let datagram = (buf.to_vec(), from);
+            let arrives_at = Instant::now() + latency;
+            match held {
+                None => {
+                    let _ = port.link.send((arrives_at, datagram));
+                }
+                Some(held) => {
+                    let inbox = port.inbox.clone();
+                    tokio::spawn(async move {
+                        time::sleep_until(arrives_at + held).await;
+                        let _ = inbox.send(datagram);
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SimSocket {
+    addr: SocketAddr,
+    network: Network,
+    rx: Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl Drop for SimSocket {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.network.inner.lock() {
+            inner.ports.remove(&self.addr);
+        }
+    }
+}
+
+impl Socket for SimSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn poll_send_to(
+        &self,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        self.network.transmit(self.addr, target, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        let mut rx = self.rx.lock().unwrap();
+        match rx.poll_recv(cx) {
+            Poll::Ready(Some((datagram, from))) => {
+                // Like udp, whatever doesn't fit in the buffer is discarded.
+                let len = datagram.len().min(buf.remaining());
+                buf.put_slice(&datagram[..len]);
+                Poll::Ready(Ok(from))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::NotConnected.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        server::Config,
+        stream::{LrcpListener, LrcpStream},
+    };
+
+    use super::*;
+
+    const LOSSY: Conditions = Conditions {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        latency: Duration::from_millis(20),
+    };
+
+    fn config() -> Config {
+        Config {
+            retransmission_timeout: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut bytes);
+        bytes
+    }
+
+    // Starts a server on the network that echoes every session's bytes back.
+    fn echo_server(network: &Network) -> SocketAddr {
+        let socket = Arc::new(network.bind());
+        let addr = socket.addr;
+        let mut listener = LrcpListener::from_socket(socket, config()).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = tokio::io::split(stream);
+                    tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+                    writer.shutdown().await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    async fn echo(network: &Network, server: SocketAddr, payload: Vec<u8>) {
+        let socket = Arc::new(network.bind());
+        let stream = LrcpStream::connect_on(socket, server, config())
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let len = payload.len();
+        let writing = tokio::spawn(async move {
+            for chunk in payload.chunks(4096) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            payload
+        });
+        let mut echoed = vec![0u8; len];
+        reader.read_exact(&mut echoed).await.unwrap();
+        assert!(writing.await.unwrap() == echoed, "echo differs");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_perfect_network() {
+        let network = Network::new(1, Default::default());
+        let server = echo_server(&network);
+        echo(&network, server, random_bytes(1, 100_000)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_echo_over_lossy_network() {
+        let network = Network::new(2, LOSSY);
+        let server = echo_server(&network);
+        echo(&network, server, random_bytes(2, 1 << 20)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_sessions_over_lossy_network() {
+        let network = Network::new(3, LOSSY);
+        let server = echo_server(&network);
+        let clients: Vec<_> = (0..8)
+            .map(|i| {
+                let network = network.clone();
+                tokio::spawn(async move {
+                    echo(&network, server, random_bytes(i, 256 << 10)).await;
+                })
+            })
+            .collect();
+        for client in clients {
+            client.await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_download_over_lossy_network() {
+        let network = Network::new(4, LOSSY);
+        let payload = random_bytes(4, 1 << 20);
+        let socket = Arc::new(network.bind());
+        let server = socket.addr;
+        let mut listener = LrcpListener::from_socket(socket, config()).unwrap();
+        let sending = payload.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(&sending).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+        let socket = Arc::new(network.bind());
+        let mut stream = LrcpStream::connect_on(socket, server, config())
+            .await
+            .unwrap();
+        // The server closes the session once we've acked everything, which
+        // we see as EOF, even if its close is lost and our session expires.
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert!(payload == received, "download differs");
+    }
+}
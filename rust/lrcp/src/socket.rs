@@ -0,0 +1,82 @@
+use std::{
+    fmt::Debug,
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+// How much a receive buffer allocates at a time. Packets are split off it and
+// share the allocation, so it's only replaced once all of them are dropped.
+pub const RECEIVE_BUFFER_LEN: usize = 64 * 1024;
+
+// A datagram socket, so sessions can run over a real UdpSocket or over a
+// simulated network in tests.
+pub trait Socket: Debug + Send + Sync + 'static {
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>>;
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>>;
+}
+
+impl Socket for UdpSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        UdpSocket::poll_recv_from(self, cx, buf)
+    }
+}
+
+pub async fn send_to(socket: &dyn Socket, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+    poll_fn(|cx| socket.poll_send_to(cx, buf, target)).await
+}
+
+pub async fn recv_from(socket: &dyn Socket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    poll_fn(|cx| {
+        let mut buf = ReadBuf::new(buf);
+        socket
+            .poll_recv_from(cx, &mut buf)
+            .map_ok(|addr| (buf.filled().len(), addr))
+    })
+    .await
+}
+
+// Receives a datagram of up to len bytes, splitting it off the buffer so it can
+// be parsed and passed on without copying.
+pub async fn recv_bytes_from(
+    socket: &dyn Socket,
+    buf: &mut BytesMut,
+    len: usize,
+) -> io::Result<(Bytes, SocketAddr)> {
+    buf.resize(len, 0);
+    let (received, addr) = recv_from(socket, buf).await?;
+    Ok((buf.split_to(received).freeze(), addr))
+}
@@ -0,0 +1,293 @@
+use std::{
+    io::{self, Read},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UdpSocket,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+};
+use tokio_util::sync::PollSender;
+
+use crate::{
+    client,
+    metrics::Metrics,
+    packet::{Data, Session},
+    server::{serve, Channel, Config},
+    socket::Socket,
+};
+
+// Accepts LRCP sessions as byte streams, much like a TcpListener, so that
+// applications never deal with packets, positions, or acks.
+#[derive(Debug)]
+pub struct LrcpListener {
+    local_addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    channels: UnboundedReceiver<Channel>,
+}
+
+impl LrcpListener {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Self::bind_with(addr, Default::default()).await
+    }
+
+    pub async fn bind_with(addr: &str, config: Config) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        Self::from_socket(Arc::new(socket), config)
+    }
+
+    // Serves sessions over the given socket, such as one on a simulated network.
+    pub fn from_socket(socket: Arc<dyn Socket>, config: Config) -> io::Result<Self> {
+        let local_addr = socket.local_addr()?;
+        let metrics = Arc::new(Metrics::default());
+        let (channels_tx, channels) = unbounded_channel();
+        let serving = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(socket, config, serving, channels_tx).await {
+                tracing::error!(?err, "serving lrcp");
+            }
+        });
+        Ok(Self {
+            local_addr,
+            metrics,
+            channels,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // The counters of the server's live sessions, which outlive the listener.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub async fn accept(&mut self) -> io::Result<(LrcpStream, SocketAddr)> {
+        match self.channels.recv().await {
+            Some(channel) => {
+                let addr = channel.addr;
+                Ok((LrcpStream::new(channel), addr))
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "lrcp server stopped",
+            )),
+        }
+    }
+}
+
+// The most bytes we hand the session in one write, so a single large write
+// can't overfill the send window.
+const MAX_WRITE: usize = 16 * 1024;
+
+// One LRCP session as an ordered, reliable byte stream. Reads return EOF once
+// the session closes or expires, and shutting down closes the session once
+// the peer has acked everything written. Writes wait while the session's send
+// window is full.
+#[derive(Debug)]
+pub struct LrcpStream {
+    session: Session,
+    rx: UnboundedReceiver<Data>,
+    tx: Option<PollSender<Vec<u8>>>,
+    // Data we have received but the reader has not yet consumed, which we
+    // unescape as it's read.
+    pending: Option<Data>,
+}
+
+impl LrcpStream {
+    pub(crate) fn new(channel: Channel) -> Self {
+        Self {
+            session: channel.session,
+            rx: channel.rx,
+            tx: Some(PollSender::new(channel.tx)),
+            pending: None,
+        }
+    }
+
+    pub async fn connect(addr: &str) -> io::Result<Self> {
+        Self::connect_with(addr, Default::default()).await
+    }
+
+    pub async fn connect_with(addr: &str, config: Config) -> io::Result<Self> {
+        let channel = client::open(addr, config).await?;
+        Ok(Self::new(channel))
+    }
+
+    // Connects to addr over the given socket, such as one on a simulated
+    // network.
+    pub async fn connect_on(
+        socket: Arc<dyn Socket>,
+        addr: SocketAddr,
+        config: Config,
+    ) -> io::Result<Self> {
+        let channel = client::open_on(socket, addr, config).await?;
+        Ok(Self::new(channel))
+    }
+
+    pub fn session(&self) -> Session {
+        self.session
+    }
+}
+
+impl AsyncRead for LrcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let pending = match &mut this.pending {
+            Some(data) if !data.is_empty() => data,
+            _ => match this.rx.poll_recv(cx) {
+                Poll::Ready(Some(data)) => this.pending.insert(data),
+                // The session has closed, which we report as EOF.
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+        let read = pending.read(buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for LrcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let tx = match &mut self.get_mut().tx {
+            Some(tx) => tx,
+            None => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        };
+        match tx.poll_reserve(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(_)) => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            Poll::Pending => return Poll::Pending,
+        }
+        let len = buf.len().min(MAX_WRITE);
+        match tx.send_item(buf[..len].to_vec()) {
+            Ok(()) => Poll::Ready(Ok(len)),
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().tx.take();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::*;
+
+    async fn recv(client: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 1000];
+        let len = client.recv(&mut buf).await.unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_line_echo() {
+        let mut listener = LrcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(listener.local_addr()).await.unwrap();
+        client.send(b"/connect/7/").await.unwrap();
+        assert_eq!(b"/ack/7/0/".to_vec(), recv(&client).await);
+        let (stream, addr) = listener.accept().await.unwrap();
+        assert_eq!(7, stream.session());
+        assert_eq!(client.local_addr().unwrap(), addr);
+        tokio::spawn(async move {
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut lines = BufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                writer.write_all(line.as_bytes()).await.unwrap();
+                writer.write_all(b"\n").await.unwrap();
+            }
+        });
+        client.send(b"/data/7/0/hel/").await.unwrap();
+        assert_eq!(b"/ack/7/3/".to_vec(), recv(&client).await);
+        client.send(b"/data/7/3/lo\n/").await.unwrap();
+        assert_eq!(b"/ack/7/6/".to_vec(), recv(&client).await);
+        assert_eq!(b"/data/7/0/hello/".to_vec(), recv(&client).await);
+        assert_eq!(b"/data/7/5/\n/".to_vec(), recv(&client).await);
+    }
+
+    #[tokio::test]
+    async fn test_large_write_is_chunked() {
+        let mut listener = LrcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(listener.local_addr()).await.unwrap();
+        client.send(b"/connect/8/").await.unwrap();
+        recv(&client).await;
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let payload = vec![b'/'; 2000];
+        stream.write_all(&payload).await.unwrap();
+        let mut received = 0;
+        while received < payload.len() {
+            let packet = recv(&client).await;
+            assert!(packet.len() < 1000);
+            let header = format!("/data/8/{}/", received).into_bytes();
+            assert!(packet.starts_with(&header));
+            received += (packet.len() - header.len() - 1) / 2;
+        }
+        assert_eq!(payload.len(), received);
+    }
+
+    #[tokio::test]
+    async fn test_write_waits_for_window() {
+        let config = Config {
+            send_window: 2000,
+            ..Default::default()
+        };
+        let mut listener = LrcpListener::bind_with("127.0.0.1:0", config)
+            .await
+            .unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(listener.local_addr()).await.unwrap();
+        client.send(b"/connect/9/").await.unwrap();
+        recv(&client).await;
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let writing = tokio::spawn(async move {
+            stream.write_all(&[b'x'; 100_000]).await.unwrap();
+        });
+        let mut received = 0;
+        while received < 2000 {
+            let packet = recv(&client).await;
+            received += packet.len() - format!("/data/9/{}//", received).len();
+        }
+        // Without acks, the writer can hand over at most the window and one
+        // more write.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!writing.is_finished());
+        while received < 100_000 {
+            client
+                .send(format!("/ack/9/{}/", received).as_bytes())
+                .await
+                .unwrap();
+            let packet = recv(&client).await;
+            let header = format!("/data/9/{}/", received);
+            if packet.starts_with(header.as_bytes()) {
+                received += packet.len() - header.len() - 1;
+            }
+        }
+        client
+            .send(format!("/ack/9/{}/", received).as_bytes())
+            .await
+            .unwrap();
+        writing.await.unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{Observation, Plate, Road, Ticket},
+    journal::{read_lines, write_lines, LineWriter},
+    policy::Day,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Issued,
+    // The plate had already had as many tickets on the day as the policy allows.
+    Suppressed { day: Day },
+}
+
+// Why a plate was or wasn't ticketed, one per line of the audit log as json.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub decision: Decision,
+    pub ticket: Ticket,
+    // The first and last days the ticket covers, by the region's policy.
+    pub days: (Day, Day),
+    // The plate's observations either side of the speeding, earliest first.
+    pub observations: [Observation; 2],
+}
+
+impl Entry {
+    pub fn days(&self) -> RangeInclusive<Day> {
+        self.days.0..=self.days.1
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [obs1, obs2] = &self.observations;
+        match self.decision {
+            Decision::Issued => write!(f, "issued ")?,
+            Decision::Suppressed { day } => write!(f, "suppressed (ticketed on day {}) ", day)?,
+        }
+        write!(
+            f,
+            "{} on road {}: mile {} at {}, mile {} at {}, {}.{:02} mph, limit {}",
+            self.ticket.plate,
+            self.ticket.road,
+            obs1.camera.mile,
+            obs1.time,
+            obs2.camera.mile,
+            obs2.time,
+            self.ticket.speed / 100,
+            self.ticket.speed % 100,
+            obs2.camera.limit,
+        )
+    }
+}
+
+// Where a region writes its audit entries, if anywhere.
+#[derive(Debug, Default)]
+pub struct Audit {
+    file: Option<File>,
+}
+
+impl Audit {
+    pub fn none() -> Self {
+        Default::default()
+    }
+
+    // Appends to the log at the path, creating it if need be.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Some(file) })
+    }
+
+    pub(crate) fn start(self) -> Auditor {
+        Auditor {
+            entries_tx: self.file.map(write_lines),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Auditor {
+    entries_tx: Option<LineWriter<Entry>>,
+}
+
+impl Auditor {
+    pub(crate) fn record(&self, entry: Entry) {
+        if let Some(entries_tx) = &self.entries_tx {
+            if let Err(entry) = entries_tx.write(entry) {
+                tracing::error!(?entry, "audit writer stopped");
+            }
+        }
+    }
+
+    // Waits until every entry recorded so far is in the file.
+    pub(crate) async fn sync(&self) {
+        if let Some(entries_tx) = &self.entries_tx {
+            entries_tx.sync().await;
+        }
+    }
+}
+
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Entry>> {
+    read_lines(path.as_ref())
+}
+
+// Picks out the entries for a plate, road or day, or any combination.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub plate: Option<Plate>,
+    pub road: Option<Road>,
+    pub day: Option<Day>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.plate
+            .as_ref()
+            .is_none_or(|plate| *plate == entry.ticket.plate)
+            && self.road.is_none_or(|road| road == entry.ticket.road)
+            && self.day.is_none_or(|day| entry.days().contains(&day))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use tokio::time::timeout;
+
+    use crate::{
+        domain::{Camera, Config, Dispatcher, Region},
+        journal::Journal,
+        testing::{settle, TempPath},
+    };
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn audit_issued_and_suppressed_tickets() {
+        let path = TempPath::new("audit.jsonl");
+        let mut region = Region::new(
+            Journal::memory(),
+            Audit::open(&path).unwrap(),
+            Config::default(),
+        );
+        let mut tickets = region.register_dispatcher(Dispatcher {
+            roads: BTreeSet::from([7]),
+        });
+        let camera = |mile| Camera {
+            road: 7,
+            mile,
+            limit: 60,
+        };
+        region.record_plate(camera(0), "AUD1T".into(), 86400);
+        region.record_plate(camera(1), "AUD1T".into(), 86430);
+        region.record_plate(camera(2), "AUD1T".into(), 86445);
+        timeout(Duration::from_secs(1), tickets.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .delivered();
+        settle(&region).await;
+        let entries = read(&path).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Decision::Issued, entries[0].decision);
+        assert_eq!(
+            [(0, 86400), (1, 86430)],
+            entries[0]
+                .observations
+                .clone()
+                .map(|obs| (obs.camera.mile, obs.time))
+        );
+        assert_eq!(Decision::Suppressed { day: 1 }, entries[1].decision);
+        assert_eq!(24000, entries[1].ticket.speed);
+        assert_eq!(
+            "suppressed (ticketed on day 1) AUD1T on road 7: mile 1 at 86430, mile 2 at 86445, 240.00 mph, limit 60",
+            entries[1].to_string()
+        );
+
+        let query = |plate: Option<&str>, road, day| Query {
+            plate: plate.map(Into::into),
+            road,
+            day,
+        };
+        assert!(query(None, None, None).matches(&entries[0]));
+        assert!(query(Some("AUD1T"), Some(7), Some(1)).matches(&entries[0]));
+        assert!(!query(Some("0THER"), None, None).matches(&entries[0]));
+        assert!(!query(None, Some(8), None).matches(&entries[0]));
+        assert!(!query(None, None, Some(0)).matches(&entries[0]));
+    }
+}
@@ -0,0 +1,48 @@
+use std::{io, path::PathBuf};
+
+use clap::Parser;
+use speed::{
+    audit::{self, Query},
+    domain::{Plate, Road},
+    policy::Day,
+};
+
+/// Explains the tickets a speed daemon issued or suppressed, from its audit
+/// log.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The audit log the daemon wrote to SPEED_AUDIT.
+    path: PathBuf,
+    /// Only entries for this plate.
+    #[arg(long)]
+    plate: Option<Plate>,
+    /// Only entries for this road.
+    #[arg(long)]
+    road: Option<Road>,
+    /// Only entries covering this day, as the daemon's policy counts them.
+    #[arg(long, allow_hyphen_values = true)]
+    day: Option<Day>,
+    /// Print the entries as json lines, as they are in the log.
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let query = Query {
+        plate: args.plate,
+        road: args.road,
+        day: args.day,
+    };
+    for entry in audit::read(&args.path)? {
+        if !query.matches(&entry) {
+            continue;
+        }
+        if args.json {
+            println!("{}", serde_json::to_string(&entry)?);
+        } else {
+            println!("{}", entry);
+        }
+    }
+    Ok(())
+}
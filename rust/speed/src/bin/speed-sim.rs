@@ -0,0 +1,311 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use speed::{
+    audit::Audit,
+    codec::MessageCodec,
+    connection::Message,
+    domain::{
+        Camera, Config, Dispatcher, Distribution, Mile, Plate, Region, Road, Speed, Ticket,
+        Timestamp,
+    },
+    journal::Journal,
+    server::Server,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinSet,
+    time,
+};
+use tokio_util::codec::Framed;
+
+/// Drives simulated cars past cameras on a speed daemon and checks that
+/// exactly the expected tickets reach its dispatchers, one per car per day.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The daemon's address. Without one, we run a daemon in this process.
+    #[arg(long)]
+    addr: Option<String>,
+    /// The number of roads.
+    #[arg(long, default_value_t = 3)]
+    roads: Road,
+    /// The number of cameras on each road.
+    #[arg(long, default_value_t = 4)]
+    cameras: u16,
+    /// The miles between neighbouring cameras.
+    #[arg(long, default_value_t = 10)]
+    spacing: Mile,
+    /// The number of dispatchers, which share the roads between them.
+    #[arg(long, default_value_t = 2)]
+    dispatchers: u16,
+    /// The number of cars, each of which drives one road.
+    #[arg(long, default_value_t = 50)]
+    cars: usize,
+    /// The number of days on which each car drives its road.
+    #[arg(long, default_value_t = 2)]
+    days: u32,
+    /// The speed limit on every road, in mph.
+    #[arg(long, default_value_t = 60)]
+    limit: Speed,
+    /// The slowest a car drives, in mph.
+    #[arg(long, default_value_t = 40)]
+    min_speed: Speed,
+    /// The fastest a car drives, in mph.
+    #[arg(long, default_value_t = 90)]
+    max_speed: Speed,
+    /// How many seconds without a ticket to wait for before reporting.
+    #[arg(long, default_value_t = 2)]
+    settle: u64,
+    /// Seeds the random cars, for a repeatable run.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// How the in-process daemon shares tickets among dispatchers:
+    /// first-available, round-robin or least-outstanding.
+    #[arg(long, default_value = "first-available")]
+    distribution: Distribution,
+}
+
+// A car driving the length of its road once a day.
+#[derive(Debug)]
+struct Car {
+    plate: Plate,
+    road: Road,
+    speed: Speed,
+    // The times it passes each camera on the road, from mile 0 up, each day.
+    passages: Vec<Vec<Timestamp>>,
+}
+
+impl Car {
+    fn drive(rng: &mut StdRng, args: &Args, plate: Plate) -> Self {
+        let road = rng.gen_range(0..args.roads);
+        let speed = rng.gen_range(args.min_speed..=args.max_speed);
+        let reversed = rng.gen_bool(0.5);
+        let duration = Self::trip(args, speed);
+        let passages = (0..args.days)
+            .map(|day| {
+                let start = day * 86400 + rng.gen_range(0..86400 - duration);
+                let mut times: Vec<Timestamp> = (0..args.cameras)
+                    .map(|camera| {
+                        let miles = f64::from(camera) * f64::from(args.spacing);
+                        start + (miles * 3600.0 / f64::from(speed)).round() as Timestamp
+                    })
+                    .collect();
+                if reversed {
+                    // It passes the last camera first.
+                    let end = start + duration;
+                    times = times.iter().map(|time| end - (time - start)).collect();
+                }
+                times
+            })
+            .collect();
+        Car {
+            plate,
+            road,
+            speed,
+            passages,
+        }
+    }
+
+    // The seconds it takes to drive the length of a road at the speed.
+    fn trip(args: &Args, speed: Speed) -> Timestamp {
+        let length = f64::from(args.spacing) * f64::from(args.cameras - 1);
+        (length * 3600.0 / f64::from(speed)).ceil() as Timestamp
+    }
+
+    // The days on which the car should be ticketed, judging each pair of
+    // cameras it passes in turn the way the daemon does.
+    fn ticketed_days(&self, args: &Args) -> Vec<Timestamp> {
+        let mut days = Vec::new();
+        for times in self.passages.iter() {
+            let mut passed: Vec<(Timestamp, Mile)> = times
+                .iter()
+                .enumerate()
+                .map(|(camera, time)| (*time, camera as Mile * args.spacing))
+                .collect();
+            passed.sort();
+            let speeding = passed.windows(2).any(|pair| {
+                let ((then, there), (now, here)) = (pair[0], pair[1]);
+                let miles = f64::from(here) - f64::from(there);
+                let hours = (f64::from(now) - f64::from(then)) / 3600.0;
+                (miles / hours).abs() - 0.5 > args.limit.into()
+            });
+            if speeding {
+                days.push(times[0] / 86400);
+            }
+        }
+        days
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<ExitCode> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+    if args.cameras < 2 || args.min_speed == 0 || args.min_speed > args.max_speed {
+        eprintln!("need at least two cameras and 0 < min speed <= max speed");
+        return Ok(ExitCode::FAILURE);
+    }
+    if args.roads == 0 {
+        eprintln!("need at least one road");
+        return Ok(ExitCode::FAILURE);
+    }
+    // The last camera's mile has to fit in a mile.
+    if u32::from(args.spacing) * u32::from(args.cameras - 1) > u32::from(Mile::MAX) {
+        eprintln!("the cameras don't fit on a road of {} miles", Mile::MAX);
+        return Ok(ExitCode::FAILURE);
+    }
+    // Each trip has to fit within its day.
+    if Car::trip(&args, args.min_speed) >= 86400 {
+        eprintln!("the slowest cars can't drive a road's cameras in a day");
+        return Ok(ExitCode::FAILURE);
+    }
+    let addr = match &args.addr {
+        Some(addr) => addr.clone(),
+        None => {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = listener.local_addr()?.to_string();
+            let config = Config {
+                distribution: args.distribution,
+                ..Default::default()
+            };
+            let mut server = Server::new(Region::new(Journal::memory(), Audit::none(), config));
+            tokio::spawn(async move { server.serve(listener).await });
+            addr
+        }
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let cars: Vec<Car> = (0..args.cars)
+        .map(|i| Car::drive(&mut rng, &args, format!("SIM{:04}", i)))
+        .collect();
+    let mut expected = BTreeSet::new();
+    for car in cars.iter() {
+        for day in car.ticketed_days(&args) {
+            expected.insert((car.plate.clone(), day));
+        }
+    }
+
+    // Dispatchers connect first, so tickets can go straight out.
+    let (tickets_tx, mut tickets_rx) = mpsc::unbounded_channel();
+    let shares = args.dispatchers.min(args.roads).max(1);
+    for i in 0..args.dispatchers {
+        let dispatcher = Dispatcher {
+            roads: (0..args.roads)
+                .filter(|road| road % shares == i % shares)
+                .collect(),
+        };
+        let mut framed = Framed::new(TcpStream::connect(&addr).await?, MessageCodec);
+        framed.send(&Message::IAmDispatcher(dispatcher)).await?;
+        let tickets_tx = tickets_tx.clone();
+        tokio::spawn(async move {
+            while let Some(message) = framed.next().await {
+                match message {
+                    Ok(Message::Ticket(ticket)) => {
+                        if tickets_tx.send((i, ticket)).is_err() {
+                            return;
+                        }
+                    }
+                    message => {
+                        eprintln!("dispatcher {} got {:?}", i, message);
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    drop(tickets_tx);
+
+    // Each camera reports the cars passing it in the order they pass.
+    let mut observations: BTreeMap<(Road, u16), Vec<(Timestamp, Plate)>> = BTreeMap::new();
+    for car in cars.iter() {
+        for times in car.passages.iter() {
+            for (camera, time) in times.iter().enumerate() {
+                observations
+                    .entry((car.road, camera as u16))
+                    .or_default()
+                    .push((*time, car.plate.clone()));
+            }
+        }
+    }
+    let mut cameras = JoinSet::new();
+    for ((road, index), mut passed) in observations {
+        passed.sort();
+        let camera = Camera {
+            road,
+            mile: index * args.spacing,
+            limit: args.limit,
+        };
+        let addr = addr.clone();
+        cameras.spawn(async move {
+            let mut framed = Framed::new(TcpStream::connect(&addr).await?, MessageCodec);
+            framed.send(&Message::IAmCamera(camera)).await?;
+            for (time, plate) in passed {
+                framed.send(&Message::Plate(plate, time)).await?;
+            }
+            Ok::<_, io::Error>(())
+        });
+    }
+    while let Some(joined) = cameras.join_next().await {
+        joined??;
+    }
+
+    let mut received: BTreeMap<(Plate, Timestamp), Vec<Ticket>> = BTreeMap::new();
+    let mut shares = vec![0; args.dispatchers.into()];
+    while let Ok(Some((i, ticket))) =
+        time::timeout(Duration::from_secs(args.settle), tickets_rx.recv()).await
+    {
+        shares[usize::from(i)] += 1;
+        let day = ticket.timestamp1 / 86400;
+        received
+            .entry((ticket.plate.clone(), day))
+            .or_default()
+            .push(ticket);
+    }
+
+    let mut missing = 0;
+    for (plate, day) in expected.iter() {
+        if !received.contains_key(&(plate.clone(), *day)) {
+            let car = cars.iter().find(|car| car.plate == *plate).unwrap();
+            println!("missing: {} on day {} at {} mph", plate, day, car.speed);
+            missing += 1;
+        }
+    }
+    let mut spurious = 0;
+    for (key, tickets) in received.iter() {
+        let extra = if expected.contains(key) {
+            &tickets[1..]
+        } else {
+            &tickets[..]
+        };
+        for ticket in extra {
+            println!("spurious: {:?}", ticket);
+            spurious += 1;
+        }
+    }
+    println!("tickets per dispatcher: {:?}", shares);
+    println!(
+        "seed {}: {} cars, {} tickets expected, {} received, {} missing, {} spurious",
+        seed,
+        cars.len(),
+        expected.len(),
+        received.values().map(Vec::len).sum::<usize>(),
+        missing,
+        spurious
+    );
+    if missing + spurious == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
@@ -0,0 +1,292 @@
+use std::{io, time::Duration};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    connection::Message,
+    domain::{Camera, Dispatcher, Ticket},
+};
+
+// Reads and writes every message in either direction, so clients can be built
+// on the same code as the server.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessageCodec;
+
+// Why a message couldn't be decoded from the buffer.
+enum Incomplete {
+    // More bytes may yet arrive.
+    Short,
+    Invalid(io::Error),
+}
+
+// Reads big-endian fields from the front of a buffer without consuming it,
+// so a message is only taken once all of it has arrived.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Incomplete> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(Incomplete::Short)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Incomplete> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Incomplete> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Incomplete> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, Incomplete> {
+        let len = self.u8()?.into();
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|err| Incomplete::Invalid(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+
+    fn message(&mut self) -> Result<Message, Incomplete> {
+        match self.u8()? {
+            0x10 => Ok(Message::Error(self.str()?)),
+            0x20 => Ok(Message::Plate(self.str()?, self.u32()?)),
+            0x21 => Ok(Message::Ticket(Ticket {
+                plate: self.str()?,
+                road: self.u16()?,
+                mile1: self.u16()?,
+                timestamp1: self.u32()?,
+                mile2: self.u16()?,
+                timestamp2: self.u32()?,
+                speed: self.u16()?,
+            })),
+            0x40 => {
+                let deciseconds = self.u32()?;
+                let duration = if deciseconds == 0 {
+                    None
+                } else {
+                    Some(Duration::from_millis(u64::from(deciseconds) * 100))
+                };
+                Ok(Message::WantHeartbeat(duration))
+            }
+            0x41 => Ok(Message::Heartbeat),
+            0x80 => Ok(Message::IAmCamera(Camera {
+                road: self.u16()?,
+                mile: self.u16()?,
+                limit: self.u16()?,
+            })),
+            0x81 => {
+                let numroads = self.u8()?;
+                let mut dispatcher: Dispatcher = Default::default();
+                for _ in 0..numroads {
+                    dispatcher.roads.insert(self.u16()?);
+                }
+                Ok(Message::IAmDispatcher(dispatcher))
+            }
+            _ => Err(Incomplete::Invalid(io::ErrorKind::Unsupported.into())),
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Message>> {
+        let mut cursor = Cursor { buf: src, pos: 0 };
+        match cursor.message() {
+            Ok(message) => {
+                let len = cursor.pos;
+                src.advance(len);
+                Ok(Some(message))
+            }
+            Err(Incomplete::Short) => Ok(None),
+            Err(Incomplete::Invalid(err)) => Err(err),
+        }
+    }
+}
+
+fn put_str(dst: &mut BytesMut, s: &str) -> io::Result<()> {
+    let len: u8 = s.len().try_into().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "strings are at most 255 bytes")
+    })?;
+    dst.put_u8(len);
+    dst.put_slice(s.as_bytes());
+    Ok(())
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> io::Result<()> {
+        match message {
+            Message::Error(msg) => {
+                dst.put_u8(0x10);
+                put_str(dst, msg)?;
+            }
+            Message::Plate(plate, timestamp) => {
+                dst.put_u8(0x20);
+                put_str(dst, plate)?;
+                dst.put_u32(*timestamp);
+            }
+            Message::Ticket(ticket) => {
+                dst.put_u8(0x21);
+                put_str(dst, &ticket.plate)?;
+                dst.put_u16(ticket.road);
+                dst.put_u16(ticket.mile1);
+                dst.put_u32(ticket.timestamp1);
+                dst.put_u16(ticket.mile2);
+                dst.put_u32(ticket.timestamp2);
+                dst.put_u16(ticket.speed);
+            }
+            Message::WantHeartbeat(duration) => {
+                // Intervals are whole deciseconds, and zero means none, so
+                // anything shorter than a decisecond turns heartbeats off.
+                let deciseconds = duration.map_or(0, |duration| {
+                    (duration.as_millis() / 100).try_into().unwrap_or(u32::MAX)
+                });
+                dst.put_u8(0x40);
+                dst.put_u32(deciseconds);
+            }
+            Message::Heartbeat => dst.put_u8(0x41),
+            Message::IAmCamera(camera) => {
+                dst.put_u8(0x80);
+                dst.put_u16(camera.road);
+                dst.put_u16(camera.mile);
+                dst.put_u16(camera.limit);
+            }
+            Message::IAmDispatcher(dispatcher) => {
+                let numroads: u8 = dispatcher.roads.len().try_into().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "dispatchers have at most 255 roads",
+                    )
+                })?;
+                dst.put_u8(0x81);
+                dst.put_u8(numroads);
+                for road in dispatcher.roads.iter() {
+                    dst.put_u16(*road);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn encode(message: &Message) -> BytesMut {
+        let mut buf = BytesMut::new();
+        MessageCodec.encode(message, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn roundtrip_every_message() {
+        let messages = [
+            Message::Error("bad".into()),
+            Message::Plate("UN1X".into(), 1000),
+            Message::Ticket(Ticket {
+                plate: "UN1X".into(),
+                road: 66,
+                mile1: 100,
+                timestamp1: 123456,
+                mile2: 110,
+                timestamp2: 123816,
+                speed: 10000,
+            }),
+            Message::WantHeartbeat(None),
+            Message::WantHeartbeat(Some(Duration::from_millis(2500))),
+            Message::Heartbeat,
+            Message::IAmCamera(Camera {
+                road: 66,
+                mile: 100,
+                limit: 60,
+            }),
+            Message::IAmDispatcher(Dispatcher {
+                roads: BTreeSet::from([66, 368, 5000]),
+            }),
+        ];
+        let mut buf = BytesMut::new();
+        for message in messages.iter() {
+            MessageCodec.encode(message, &mut buf).unwrap();
+        }
+        for message in messages.iter() {
+            let decoded = MessageCodec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(*message, decoded);
+        }
+        assert!(buf.is_empty());
+        assert!(MessageCodec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn encode_matches_spec() {
+        let ticket = Message::Ticket(Ticket {
+            plate: "UN1X".into(),
+            road: 66,
+            mile1: 100,
+            timestamp1: 123456,
+            mile2: 110,
+            timestamp2: 123816,
+            speed: 10000,
+        });
+        assert_eq!(
+            &[
+                0x21, 0x04, 0x55, 0x4e, 0x31, 0x58, 0x00, 0x42, 0x00, 0x64, 0x00, 0x01, 0xe2, 0x40,
+                0x00, 0x6e, 0x00, 0x01, 0xe3, 0xa8, 0x27, 0x10
+            ][..],
+            &encode(&ticket)[..]
+        );
+        let dispatcher = Message::IAmDispatcher(Dispatcher {
+            roads: BTreeSet::from([66, 368, 5000]),
+        });
+        assert_eq!(
+            &[0x81, 0x03, 0x00, 0x42, 0x01, 0x70, 0x13, 0x88][..],
+            &encode(&dispatcher)[..]
+        );
+        let beat = Message::WantHeartbeat(Some(Duration::from_millis(1243 * 100)));
+        assert_eq!(&[0x40, 0x00, 0x00, 0x04, 0xdb][..], &encode(&beat)[..]);
+    }
+
+    #[test]
+    fn decode_waits_for_whole_message() {
+        let whole = encode(&Message::Plate("RE05BKG".into(), 123456));
+        let mut buf = BytesMut::new();
+        for byte in &whole[..whole.len() - 1] {
+            buf.put_u8(*byte);
+            assert!(MessageCodec.decode(&mut buf).unwrap().is_none());
+        }
+        buf.put_u8(whole[whole.len() - 1]);
+        match MessageCodec.decode(&mut buf).unwrap() {
+            Some(Message::Plate(plate, 123456)) => assert_eq!("RE05BKG", plate),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        let mut buf = BytesMut::from(&[0x99][..]);
+        assert!(MessageCodec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::from(&[0x10, 0x01, 0xff][..]);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            MessageCodec.decode(&mut buf).unwrap_err().kind()
+        );
+        let mut buf = BytesMut::new();
+        let long = Message::Error("x".repeat(256));
+        assert!(MessageCodec.encode(&long, &mut buf).is_err());
+    }
+}
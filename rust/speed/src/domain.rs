@@ -1,5 +1,22 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    str::FromStr,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, watch,
+    },
+    task::JoinSet,
+};
+
+use crate::{
+    audit::{self, Audit, Auditor, Decision},
+    journal::{Event, Journal, Journaler},
+    policy::{Day, TicketPolicy},
+};
 
 pub type Timestamp = u32;
 pub type Road = u16;
@@ -7,19 +24,19 @@ pub type Mile = u16;
 pub type Speed = u16;
 pub type Plate = String;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Camera {
     pub road: Road,
     pub mile: Mile,
     pub limit: Speed,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Dispatcher {
     pub roads: BTreeSet<Road>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Ticket {
     pub plate: Plate,
     pub road: Road,
@@ -30,46 +47,346 @@ pub struct Ticket {
     pub speed: Speed,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Observation {
     pub camera: Camera,
     pub plate: Plate,
     pub time: Timestamp,
 }
 
+// The speed limit of each road, fixed by the first camera to report one, so
+// every ticket on a road is judged against the same limit.
+#[derive(Clone, Debug, Default)]
+pub struct Roads {
+    limits: BTreeMap<Road, Speed>,
+}
+
+// A camera reported a different limit for a road than the one registered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LimitConflict {
+    pub road: Road,
+    pub limit: Speed,
+    pub reported: Speed,
+}
+
+impl fmt::Display for LimitConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "road {} has limit {}, not {}",
+            self.road, self.limit, self.reported
+        )
+    }
+}
+
+impl std::error::Error for LimitConflict {}
+
+impl Roads {
+    // Registers the camera's limit for its road, unless the road already has
+    // a different one.
+    pub fn register(&mut self, camera: Camera) -> Result<(), LimitConflict> {
+        let limit = *self.limits.entry(camera.road).or_insert(camera.limit);
+        if limit == camera.limit {
+            Ok(())
+        } else {
+            Err(LimitConflict {
+                road: camera.road,
+                limit,
+                reported: camera.limit,
+            })
+        }
+    }
+
+    // The road's registered limit, registering the camera's if there's none.
+    pub fn limit(&mut self, camera: Camera) -> Speed {
+        *self.limits.entry(camera.road).or_insert(camera.limit)
+    }
+}
+
+// How many seconds behind the newest observation we keep observations. The
+// default keeps them all.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retention(pub Timestamp);
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self(Timestamp::MAX)
+    }
+}
+
+// The observations of each plate on each road, keeping the camera that saw
+// it at each time, indexed by time as well so the oldest can be forgotten once
+// they fall outside the retention window.
+#[derive(Debug, Default)]
+struct Records {
+    retention: Retention,
+    newest: Timestamp,
+    by_plate: BTreeMap<Plate, BTreeMap<Road, BTreeMap<Timestamp, Camera>>>,
+    by_time: BTreeMap<Timestamp, Vec<(Plate, Road)>>,
+    evicted: usize,
+}
+
+impl Records {
+    fn new(retention: Retention) -> Self {
+        Self {
+            retention,
+            ..Default::default()
+        }
+    }
+
+    // The earliest time we still keep observations for.
+    fn cutoff(&self) -> Timestamp {
+        self.newest.saturating_sub(self.retention.0)
+    }
+
+    // Records the observation, unless it's already too old to keep, returning
+    // the plate's observations on the road if it was new.
+    fn insert(&mut self, obs: &Observation) -> Option<&BTreeMap<Timestamp, Camera>> {
+        if obs.time < self.cutoff() {
+            tracing::info!("observation is too old to keep");
+            self.evicted += 1;
+            return None;
+        }
+        self.newest = self.newest.max(obs.time);
+        self.evict_before(self.cutoff());
+        let by_timestamp = self
+            .by_plate
+            .entry(obs.plate.clone())
+            .or_default()
+            .entry(obs.camera.road)
+            .or_default();
+        // We keep the first observation at any time, as a plate can't be in
+        // two places at once.
+        match by_timestamp.entry(obs.time) {
+            Entry::Occupied(entry) => {
+                if entry.get().mile != obs.camera.mile {
+                    tracing::error!(mile = entry.get().mile, "inconsistent observations");
+                }
+                return None;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(obs.camera);
+            }
+        }
+        self.by_time
+            .entry(obs.time)
+            .or_default()
+            .push((obs.plate.clone(), obs.camera.road));
+        self.by_plate
+            .get(&obs.plate)
+            .and_then(|by_road| by_road.get(&obs.camera.road))
+    }
+
+    fn evict_before(&mut self, cutoff: Timestamp) {
+        while let Some(entry) = self.by_time.first_entry() {
+            if *entry.key() >= cutoff {
+                break;
+            }
+            let (time, observed) = entry.remove_entry();
+            for (plate, road) in observed {
+                let by_road = self.by_plate.get_mut(&plate).expect("indexed plate");
+                let by_timestamp = by_road.get_mut(&road).expect("indexed road");
+                by_timestamp.remove(&time);
+                self.evicted += 1;
+                if by_timestamp.is_empty() {
+                    by_road.remove(&road);
+                    if by_road.is_empty() {
+                        self.by_plate.remove(&plate);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A ticket for a dispatcher, which acknowledges it once it's written to the
+// socket. Dropping it unacknowledged sends the ticket to another dispatcher.
+#[derive(Debug)]
+pub struct Delivery {
+    pub ticket: Ticket,
+    ack_tx: oneshot::Sender<()>,
+}
+
+impl Delivery {
+    pub fn delivered(self) {
+        // The region only stops listening when it's shutting down.
+        let _ = self.ack_tx.send(());
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Config {
+    // Observations older than the retention window are forgotten, so a pair
+    // of them further apart than the window is never ticketed.
+    pub retention: Retention,
+    pub distribution: Distribution,
+    pub policy: TicketPolicy,
+}
+
+// A pair of a plate's observations showing it speeding, for the assessor to
+// ticket or not.
+#[derive(Clone, Debug, PartialEq)]
+struct Violation {
+    ticket: Ticket,
+    observations: [Observation; 2],
+    // Found replaying the journal, so audited before the restart.
+    replayed: bool,
+}
+
 #[derive(Clone, Debug)]
 struct Dispatch {
     dispatcher: Dispatcher,
-    tickets_tx: mpsc::Sender<Ticket>,
+    tickets_tx: mpsc::Sender<Delivery>,
 }
 
 #[derive(Debug)]
 pub struct Region {
+    roads: Roads,
     observations_tx: mpsc::UnboundedSender<Observation>,
     dispatches_tx: mpsc::UnboundedSender<Dispatch>,
+    evicted_rx: watch::Receiver<usize>,
+    journaler: Journaler,
+    auditor: Auditor,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::new(Journal::memory(), Audit::none(), Config::default())
+    }
 }
 
 impl Region {
-    pub fn new() -> Self {
+    // Starts the region from the journal's events, recording new ones to it.
+    // Replayed observations rebuild the records and road limits, replayed
+    // tickets keep plates from being ticketed twice for a day, and tickets
+    // that were issued but never dispatched are queued for a dispatcher.
+    // Every ticket issued or suppressed is explained in the audit log.
+    pub fn new(mut journal: Journal, audit: Audit, config: Config) -> Self {
+        // Observations outside the retention window would only be evicted as
+        // soon as they were replayed, so we drop them from the journal first.
+        let newest = journal
+            .events()
+            .iter()
+            .filter_map(|event| match event {
+                Event::Observed(obs) => Some(obs.time),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let cutoff = newest.saturating_sub(config.retention.0);
+        let compacted =
+            journal.compact(|event| !matches!(event, Event::Observed(obs) if obs.time < cutoff));
+        if let Err(err) = compacted {
+            tracing::error!(?err, "compacting journal");
+        }
+        let mut roads = Roads::default();
+        let mut observed = Vec::new();
+        let mut issued = Vec::new();
+        let mut dispatched: BTreeMap<(Plate, Timestamp), usize> = BTreeMap::new();
+        for event in journal.events() {
+            match event {
+                Event::Observed(obs) => {
+                    roads.limit(obs.camera);
+                    observed.push(obs.clone());
+                }
+                Event::Issued(ticket) => issued.push(ticket.clone()),
+                Event::Dispatched(ticket) => {
+                    *dispatched
+                        .entry((ticket.plate.clone(), ticket.timestamp1))
+                        .or_default() += 1;
+                }
+            }
+        }
+        let undispatched = issued
+            .iter()
+            .filter(
+                |ticket| match dispatched.get_mut(&(ticket.plate.clone(), ticket.timestamp1)) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                },
+            )
+            .cloned()
+            .collect();
+        tracing::info!(
+            observed = observed.len(),
+            issued = issued.len(),
+            "replaying journal"
+        );
+        let journaler = journal.start();
+        let auditor = audit.start();
+
         let (observations_tx, observations_rx) = mpsc::unbounded_channel();
         let (violations_tx, violations_rx) = mpsc::channel(1);
-        tokio::spawn(Self::do_record_observations(observations_rx, violations_tx));
+        let (evicted_tx, evicted_rx) = watch::channel(0);
+        tokio::spawn(Self::do_record_observations(
+            observations_rx,
+            violations_tx,
+            observed,
+            Records::new(config.retention),
+            config.policy,
+            evicted_tx,
+            journaler.clone(),
+        ));
 
         let (tickets_tx, tickets_rx) = mpsc::unbounded_channel();
-        tokio::spawn(Self::do_assess_violations(violations_rx, tickets_tx));
+        tokio::spawn(Self::do_assess_violations(
+            violations_rx,
+            tickets_tx,
+            issued,
+            config.policy,
+            journaler.clone(),
+            auditor.clone(),
+        ));
 
         let (dispatches_tx, dispatches_rx) = mpsc::unbounded_channel();
-        tokio::spawn(Self::do_manage_dispatchers(dispatches_rx, tickets_rx));
+        tokio::spawn(Self::do_manage_dispatchers(
+            dispatches_rx,
+            tickets_rx,
+            undispatched,
+            config.distribution,
+            journaler.clone(),
+        ));
 
         Self {
+            roads,
             observations_tx,
             dispatches_tx,
+            evicted_rx,
+            journaler,
+            auditor,
         }
     }
 
+    // Waits until everything the region's tasks have journaled or audited so
+    // far is in the files.
+    pub async fn sync(&self) {
+        self.journaler.sync().await;
+        self.auditor.sync().await;
+    }
+
+    // The number of observations forgotten for falling outside the retention
+    // window.
+    pub fn evicted(&self) -> usize {
+        *self.evicted_rx.borrow()
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn register_camera(&mut self, camera: Camera) -> Result<(), LimitConflict> {
+        self.roads.register(camera)
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn record_plate(&mut self, camera: Camera, plate: Plate, time: Timestamp) {
         tracing::info!("recording plate");
+        // Judge every observation on a road by the road's registered limit,
+        // whatever its camera claims.
+        let camera = Camera {
+            limit: self.roads.limit(camera),
+            ..camera
+        };
         self.observations_tx
             .send(Observation {
                 camera,
@@ -82,62 +399,98 @@ impl Region {
     #[tracing::instrument(skip_all)]
     async fn do_record_observations(
         mut observations_rx: mpsc::UnboundedReceiver<Observation>,
-        violations_tx: mpsc::Sender<Ticket>,
+        violations_tx: mpsc::Sender<Violation>,
+        replayed: Vec<Observation>,
+        mut records: Records,
+        policy: TicketPolicy,
+        evicted_tx: watch::Sender<usize>,
+        journaler: Journaler,
     ) {
-        let mut records = BTreeMap::new();
+        // Replayed observations may find violations again, but the assessor
+        // already knows about the tickets they led to.
+        let mut replayed = replayed.into_iter();
         loop {
-            let obs = observations_rx.recv().await;
-            if obs.is_none() {
-                tracing::error!("observations channel closed");
-                break;
-            }
-            let (ticket1, ticket2) = Self::record_observation(&mut records, &obs.unwrap());
-            if let Some(ticket1) = ticket1 {
-                tracing::info!(?ticket1, "sending violation");
-                if let Err(err) = violations_tx.send(ticket1.clone()).await {
-                    tracing::error!(?ticket1, ?err, "error sending violation");
-                    return;
+            let (obs, replaying) = match replayed.next() {
+                Some(obs) => (obs, true),
+                None => {
+                    let obs = observations_rx.recv().await;
+                    if obs.is_none() {
+                        tracing::error!("observations channel closed");
+                        break;
+                    }
+                    let obs = obs.unwrap();
+                    journaler.record(Event::Observed(obs.clone()));
+                    (obs, false)
                 }
-            }
-            if let Some(ticket2) = ticket2 {
-                tracing::info!(?ticket2, "sending violation");
-                if let Err(err) = violations_tx.send(ticket2.clone()).await {
-                    tracing::error!(?ticket2, ?err, "error sending violation");
+            };
+            let (violation1, violation2) = Self::record_observation(&mut records, &obs, &policy);
+            evicted_tx.send_if_modified(|evicted| {
+                let modified = *evicted != records.evicted;
+                *evicted = records.evicted;
+                modified
+            });
+            for mut violation in [violation1, violation2].into_iter().flatten() {
+                tracing::info!(ticket = ?violation.ticket, "sending violation");
+                violation.replayed = replaying;
+                if let Err(err) = violations_tx.send(violation).await {
+                    tracing::error!(?err, "error sending violation");
                     return;
                 }
             }
         }
     }
 
-    #[tracing::instrument(skip(records))]
+    // Records the observation, returning the violations it shows, if any,
+    // with the observation it's paired with in each.
+    #[tracing::instrument(skip(records, policy))]
     fn record_observation(
-        records: &mut BTreeMap<Plate, BTreeMap<Road, BTreeMap<Timestamp, Mile>>>,
+        records: &mut Records,
         obs: &Observation,
-    ) -> (Option<Ticket>, Option<Ticket>) {
-        let by_road = records.entry(obs.plate.clone()).or_default();
-        let by_timestamp = by_road.entry(obs.camera.road).or_default();
-        if by_timestamp.insert(obs.time, obs.camera.mile).is_none() {
+        policy: &TicketPolicy,
+    ) -> (Option<Violation>, Option<Violation>) {
+        if let Some(by_timestamp) = records.insert(obs) {
             tracing::info!("recorded observation");
-            let ticket1 = if let Some((then, there)) = by_timestamp.range(0..obs.time).last() {
-                Self::compute_ticket(obs.camera, &obs.plate, obs.time, *then, *there)
-            } else {
-                None
+            // We compare with the nearest observations either side that are
+            // far enough apart to judge, skipping any closer ones between.
+            let interval = policy.min_interval.max(1);
+            let earlier = obs
+                .time
+                .checked_sub(interval)
+                .and_then(|latest| by_timestamp.range(..=latest).last());
+            let later = obs
+                .time
+                .checked_add(interval)
+                .and_then(|earliest| by_timestamp.range(earliest..).next());
+            let violation = |(then, there): (&Timestamp, &Camera)| {
+                let ticket = Self::compute_ticket(
+                    policy, obs.camera, &obs.plate, obs.time, *then, there.mile,
+                )?;
+                let other = Observation {
+                    camera: *there,
+                    plate: obs.plate.clone(),
+                    time: *then,
+                };
+                let observations = if *then < obs.time {
+                    [other, obs.clone()]
+                } else {
+                    [obs.clone(), other]
+                };
+                Some(Violation {
+                    ticket,
+                    observations,
+                    replayed: false,
+                })
             };
-            let ticket2 = if let Some((then, there)) = by_timestamp.range(obs.time + 1..).next() {
-                Self::compute_ticket(obs.camera, &obs.plate, obs.time, *then, *there)
-            } else {
-                None
-            };
-            (ticket1, ticket2)
+            (earlier.and_then(violation), later.and_then(violation))
         } else {
-            tracing::error!("inconsistent observations");
             (None, None)
         }
     }
 
     // returns a ticket if the observations indicate a speed violation.
-    #[tracing::instrument]
+    #[tracing::instrument(skip(policy))]
     fn compute_ticket(
+        policy: &TicketPolicy,
         camera: Camera,
         plate: &Plate,
         now: Timestamp,
@@ -149,7 +502,7 @@ impl Region {
         let hours = (f64::from(now) - f64::from(then)) / 3600.0;
         let velocity: f64 = miles / hours;
         let speed = velocity.abs();
-        if speed - 0.5 > camera.limit.into() {
+        if policy.speeding(speed, camera.limit) {
             tracing::info!("computed ticket");
             let (mile1, mile2, timestamp1, timestamp2) = if then < now {
                 (there, camera.mile, then, now)
@@ -171,39 +524,78 @@ impl Region {
 
     #[tracing::instrument(skip_all)]
     async fn do_assess_violations(
-        mut violations_rx: mpsc::Receiver<Ticket>,
+        mut violations_rx: mpsc::Receiver<Violation>,
         tickets_tx: mpsc::UnboundedSender<Ticket>,
+        issued: Vec<Ticket>,
+        policy: TicketPolicy,
+        journaler: Journaler,
+        auditor: Auditor,
     ) {
-        let mut tickets_issued: BTreeMap<Plate, BTreeSet<Timestamp>> = BTreeMap::new();
+        // The number of tickets each plate has been issued on each day.
+        let mut tickets_issued: BTreeMap<Plate, BTreeMap<Day, u32>> = BTreeMap::new();
+        // The journaled tickets, which replayed violations are matched to
+        // rather than counted again.
+        let mut journaled: BTreeSet<(Plate, Timestamp, Timestamp)> = BTreeSet::new();
+        for ticket in issued {
+            let counts = tickets_issued.entry(ticket.plate.clone()).or_default();
+            for day in policy.days(&ticket) {
+                *counts.entry(day).or_default() += 1;
+            }
+            journaled.insert((ticket.plate, ticket.timestamp1, ticket.timestamp2));
+        }
         'outer: loop {
-            let ticket = violations_rx.recv().await;
-            if ticket.is_none() {
+            let violation = violations_rx.recv().await;
+            if violation.is_none() {
                 tracing::error!("violations channel closed");
                 break;
             }
-            let ticket = ticket.unwrap();
-            let day1 = ticket.timestamp1 / 86400;
-            let day2 = ticket.timestamp2 / 86400;
-            let tickets_issued_days = tickets_issued.entry(ticket.plate.clone()).or_default();
-            for day in day1..=day2 {
-                if tickets_issued_days.contains(&day) {
+            let Violation {
+                ticket,
+                observations,
+                replayed,
+            } = violation.unwrap();
+            if replayed
+                && journaled.remove(&(ticket.plate.clone(), ticket.timestamp1, ticket.timestamp2))
+            {
+                continue;
+            }
+            let days = policy.days(&ticket);
+            let counts = tickets_issued.entry(ticket.plate.clone()).or_default();
+            for day in days.clone() {
+                if counts.get(&day).copied().unwrap_or(0) >= policy.tickets_per_day {
+                    if !replayed {
+                        tracing::info!(?ticket, day, "suppressing ticket");
+                        auditor.record(audit::Entry {
+                            decision: Decision::Suppressed { day },
+                            ticket,
+                            days: days.into_inner(),
+                            observations,
+                        });
+                    }
                     continue 'outer;
                 }
             }
             tracing::info!(?ticket, "issuing ticket");
+            journaler.record(Event::Issued(ticket.clone()));
+            auditor.record(audit::Entry {
+                decision: Decision::Issued,
+                ticket: ticket.clone(),
+                days: days.clone().into_inner(),
+                observations,
+            });
             if let Err(err) = tickets_tx.send(ticket) {
                 tracing::error!(?err, "error issuing ticket");
                 break;
             }
-            for day in day1..=day2 {
-                tickets_issued_days.insert(day);
+            for day in days {
+                *counts.entry(day).or_default() += 1;
             }
         }
         tracing::error!("stopping");
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn register_dispatcher(&mut self, dispatcher: Dispatcher) -> mpsc::Receiver<Ticket> {
+    pub fn register_dispatcher(&mut self, dispatcher: Dispatcher) -> mpsc::Receiver<Delivery> {
         let (tickets_tx, tickets_rx) = mpsc::channel(1);
         let dispatch = Dispatch {
             dispatcher,
@@ -219,10 +611,16 @@ impl Region {
     async fn do_manage_dispatchers(
         mut dispatches_rx: mpsc::UnboundedReceiver<Dispatch>,
         mut tickets_rx: mpsc::UnboundedReceiver<Ticket>,
+        undispatched: Vec<Ticket>,
+        distribution: Distribution,
+        journaler: Journaler,
     ) {
         let mut unsent: BTreeMap<Road, VecDeque<Ticket>> = BTreeMap::new();
-        let mut dispatchers: BTreeMap<Road, VecDeque<mpsc::Sender<Ticket>>> = BTreeMap::new();
-        'select: loop {
+        for ticket in undispatched {
+            unsent.entry(ticket.road).or_default().push_back(ticket);
+        }
+        let mut dispatchers = Dispatchers::new(distribution);
+        loop {
             tokio::select! {
                 ticket = tickets_rx.recv() => {
                     if ticket.is_none() {
@@ -230,57 +628,35 @@ impl Region {
                         break;
                     }
                     let ticket = ticket.unwrap();
-                    let road_dispatchers = dispatchers.entry(ticket.road).or_default();
-                    loop {
-                        let dispatcher = road_dispatchers.front();
-                        if dispatcher.is_none() {
-                            break;
-                        }
-                        let dispatcher = dispatcher.unwrap();
-                        tracing::info!(?ticket, "trying to dispatch ticket");
-                        if let Err(err) = dispatcher.send(ticket.clone()).await {
-                            tracing::warn!(?ticket, ?err, "failed to dispatch ticket");
-                            road_dispatchers.pop_front();
-                        } else {
-                            tracing::info!(?ticket, "dispatched ticket");
-                            continue 'select;
-                        }
+                    let road = ticket.road;
+                    unsent.entry(road).or_default().push_back(ticket);
+                    dispatchers.dispatch_unsent(&mut unsent, [road]);
+                }
+                Some(delivery) = dispatchers.deliveries.join_next() => {
+                    let (id, ticket, delivered) = delivery.expect("await delivery");
+                    dispatchers.settled(id);
+                    if delivered {
+                        tracing::info!(?ticket, "delivered ticket");
+                        journaler.record(Event::Dispatched(ticket));
+                    } else {
+                        tracing::warn!(?ticket, "ticket was not delivered, rerouting");
+                        unsent.entry(ticket.road).or_default().push_front(ticket);
                     }
-                    tracing::info!(?ticket, "recording ticket to send later");
-                    unsent.entry(ticket.road).or_default().push_back(ticket);
+                    // The dispatcher has taken its ticket off its channel, so
+                    // it may have room for one that was waiting.
+                    let roads: Vec<_> = unsent.keys().copied().collect();
+                    dispatchers.dispatch_unsent(&mut unsent, roads);
                 }
                 dispatch = dispatches_rx.recv() => {
                     if dispatch.is_none() {
                         tracing::info!("dispatch receiver channel closed, stopping");
                         break;
                     }
-                    let Dispatch { dispatcher, tickets_tx } = dispatch.unwrap();
-                    tracing::info!(?dispatcher, "received dispatcher");
-                    for road in dispatcher.roads.iter() {
-                        tracing::info!(?dispatcher, ?road, "registering dispatcher");
-                        let road_dispatchers = dispatchers.entry(*road).or_default();
-                        road_dispatchers.push_back(tickets_tx.clone());
-                    }
-                    for road in dispatcher.roads.iter() {
-                        tracing::info!(?dispatcher, ?road, "checking for unsent");
-                        if let Some(tickets) = unsent.get_mut(&road) {
-                            tracing::info!(?dispatcher, ?road, "processing unsent tickets");
-                            loop {
-                                let ticket = tickets.pop_front();
-                                if ticket.is_none() {
-                                    tracing::info!(?dispatcher, ?road, "no more unsent tickets");
-                                    break;
-                                }
-                                let ticket = ticket.unwrap();
-                                tracing::info!(?dispatcher, ?road, ?ticket, "trying to send ticket");
-                                if let Err(err) = tickets_tx.send(ticket.clone()).await {
-                                    tracing::error!(?dispatcher, ?road, ?ticket, ?err, "error sending ticket, requeueing");
-                                    tickets.push_front(ticket);
-                                    continue 'select;
-                                }
-                            }
-                        }
-                    }
+                    let dispatch = dispatch.unwrap();
+                    let roads = dispatch.dispatcher.roads.clone();
+                    tracing::info!(dispatcher = ?dispatch.dispatcher, "received dispatcher");
+                    dispatchers.register(dispatch);
+                    dispatchers.dispatch_unsent(&mut unsent, roads);
                 }
             }
         }
@@ -288,16 +664,171 @@ impl Region {
     }
 }
 
+// How the tickets for a road are shared among its dispatchers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Distribution {
+    // The longest-connected dispatcher gets every ticket.
+    #[default]
+    FirstAvailable,
+    // The dispatchers take turns.
+    RoundRobin,
+    // The dispatcher with the fewest unacknowledged tickets gets the next,
+    // so a slow one doesn't hold up the rest.
+    LeastOutstanding,
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-available" => Ok(Self::FirstAvailable),
+            "round-robin" => Ok(Self::RoundRobin),
+            "least-outstanding" => Ok(Self::LeastOutstanding),
+            _ => Err(format!("unknown distribution {}", s)),
+        }
+    }
+}
+
+// The dispatching task's view of the dispatchers: the live ones for each
+// road, in the order they connected, and the tickets each has yet to
+// acknowledge.
+#[derive(Debug)]
+struct Dispatchers {
+    distribution: Distribution,
+    next_id: usize,
+    by_road: BTreeMap<Road, VecDeque<(usize, mpsc::Sender<Delivery>)>>,
+    outstanding: BTreeMap<usize, usize>,
+    // Tickets stay ours until their dispatcher has written them out.
+    deliveries: JoinSet<(usize, Ticket, bool)>,
+}
+
+impl Dispatchers {
+    fn new(distribution: Distribution) -> Self {
+        Self {
+            distribution,
+            next_id: 0,
+            by_road: Default::default(),
+            outstanding: Default::default(),
+            deliveries: JoinSet::new(),
+        }
+    }
+
+    fn register(&mut self, dispatch: Dispatch) {
+        let id = self.next_id;
+        self.next_id += 1;
+        for road in dispatch.dispatcher.roads.iter() {
+            tracing::info!(id, ?road, "registering dispatcher");
+            self.by_road
+                .entry(*road)
+                .or_default()
+                .push_back((id, dispatch.tickets_tx.clone()));
+        }
+    }
+
+    // Hands the ticket to a live dispatcher for its road, forgetting any that
+    // have gone and passing over any whose channel is full, and awaits its
+    // delivery in the background. Returns the ticket if no one can take it.
+    fn dispatch(&mut self, ticket: Ticket) -> Option<Ticket> {
+        let road_dispatchers = self.by_road.entry(ticket.road).or_default();
+        // The order to try them in, passing over any that are full for the
+        // next.
+        let mut candidates: Vec<usize> = road_dispatchers.iter().map(|(id, _)| *id).collect();
+        if self.distribution == Distribution::LeastOutstanding {
+            candidates.sort_by_key(|id| self.outstanding.get(id).copied().unwrap_or(0));
+        }
+        for id in candidates {
+            let i = road_dispatchers
+                .iter()
+                .position(|(candidate, _)| *candidate == id)
+                .expect("candidate is registered");
+            tracing::info!(id, ?ticket, "trying to dispatch ticket");
+            let (ack_tx, ack_rx) = oneshot::channel();
+            let delivery = Delivery {
+                ticket: ticket.clone(),
+                ack_tx,
+            };
+            match road_dispatchers[i].1.try_send(delivery) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::info!(id, ?ticket, "dispatcher is busy");
+                    continue;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    tracing::warn!(id, ?ticket, "failed to dispatch ticket");
+                    road_dispatchers.remove(i);
+                    continue;
+                }
+            }
+            if self.distribution == Distribution::RoundRobin {
+                // The dispatchers passed over keep their place at the front.
+                let taken = road_dispatchers.remove(i).expect("candidate is registered");
+                road_dispatchers.push_back(taken);
+            }
+            *self.outstanding.entry(id).or_default() += 1;
+            self.deliveries
+                .spawn(async move { (id, ticket, ack_rx.await.is_ok()) });
+            return None;
+        }
+        Some(ticket)
+    }
+
+    // Dispatches the unsent tickets for the roads in order, stopping at the
+    // first on each road that no one can take.
+    fn dispatch_unsent(
+        &mut self,
+        unsent: &mut BTreeMap<Road, VecDeque<Ticket>>,
+        roads: impl IntoIterator<Item = Road>,
+    ) {
+        for road in roads {
+            if let Some(tickets) = unsent.get_mut(&road) {
+                while let Some(ticket) = tickets.pop_front() {
+                    if let Some(ticket) = self.dispatch(ticket) {
+                        tracing::info!(?road, ?ticket, "recording ticket to send later");
+                        tickets.push_front(ticket);
+                        break;
+                    }
+                }
+                if tickets.is_empty() {
+                    unsent.remove(&road);
+                }
+            }
+        }
+    }
+
+    // Notes that the dispatcher has delivered or dropped a ticket.
+    fn settled(&mut self, id: usize) {
+        if let Some(outstanding) = self.outstanding.get_mut(&id) {
+            *outstanding -= 1;
+            if *outstanding == 0 {
+                self.outstanding.remove(&id);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{collections::BTreeSet, time::Duration};
 
-    use tokio::time::sleep;
+    use tokio::{
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
     use tracing_test::traced_test;
 
-    use crate::domain::Region;
+    use crate::{
+        audit::Audit,
+        domain::Region,
+        journal::{Event, Journal},
+        policy::TicketPolicy,
+        testing::{settle, TempPath},
+    };
 
-    use super::{Camera, Mile, Plate, Ticket, Timestamp};
+    use super::{
+        Camera, Config, Delivery, Dispatcher, Distribution, LimitConflict, Mile, Observation,
+        Plate, Records, Retention, Roads, Ticket, Timestamp,
+    };
 
     #[test]
     fn compute_ticket() {
@@ -322,48 +853,796 @@ mod tests {
         };
         assert_eq!(
             Some(ticket),
-            Region::compute_ticket(camera, &plate, now, then, there)
+            Region::compute_ticket(&TicketPolicy::default(), camera, &plate, now, then, there)
         );
     }
 
+    #[test]
+    fn register_roads() {
+        let camera = |road, limit| Camera {
+            road,
+            mile: 0,
+            limit,
+        };
+        let mut roads = Roads::default();
+        assert_eq!(Ok(()), roads.register(camera(1, 60)));
+        assert_eq!(Ok(()), roads.register(camera(1, 60)));
+        assert_eq!(Ok(()), roads.register(camera(2, 100)));
+        assert_eq!(
+            Err(LimitConflict {
+                road: 1,
+                limit: 60,
+                reported: 100
+            }),
+            roads.register(camera(1, 100))
+        );
+        assert_eq!(60, roads.limit(camera(1, 100)));
+        assert_eq!(80, roads.limit(camera(3, 80)));
+        assert_eq!(80, roads.limit(camera(3, 60)));
+    }
+
     #[tokio::test]
     #[traced_test]
-    async fn replicate_bug() {
+    async fn tickets_use_registered_limit() {
+        let mut region = Region::default();
+        let slow = Camera {
+            road: 7,
+            mile: 0,
+            limit: 60,
+        };
+        let fast = Camera {
+            road: 7,
+            mile: 1,
+            limit: 100,
+        };
+        region.register_camera(slow).unwrap();
+        assert!(region.register_camera(fast).is_err());
+        let mut tickets = region.register_dispatcher(Dispatcher {
+            roads: BTreeSet::from([7]),
+        });
+        // 1 mile in 45 seconds is 80 mph, legal by the conflicting camera's
+        // limit but not the road's.
+        region.record_plate(slow, "AB12CDE".into(), 0);
+        region.record_plate(fast, "AB12CDE".into(), 45);
+        let ticket = deliver(&mut tickets).await;
+        assert_eq!(8000, ticket.speed);
+    }
+
+    // Receives the next ticket, acknowledging its delivery.
+    async fn deliver(tickets: &mut mpsc::Receiver<Delivery>) -> Ticket {
+        let delivery = timeout(Duration::from_secs(1), tickets.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let ticket = delivery.ticket.clone();
+        delivery.delivered();
+        ticket
+    }
+
+    // Records the observations in order, then collects every ticket the
+    // region dispatches. The clock is paused, so the timeout only fires once
+    // every task in the pipeline is idle.
+    async fn dispatched(
+        mut region: Region,
+        roads: &[u16],
+        observations: &[(Camera, &str, Timestamp)],
+    ) -> Vec<Ticket> {
+        let mut tickets_rx = region.register_dispatcher(Dispatcher {
+            roads: roads.iter().copied().collect(),
+        });
+        for (camera, plate, time) in observations {
+            region.record_plate(*camera, plate.to_string(), *time);
+        }
+        let mut tickets = Vec::new();
+        while let Ok(delivery) = timeout(Duration::from_secs(60), tickets_rx.recv()).await {
+            let delivery = delivery.unwrap();
+            tickets.push(delivery.ticket.clone());
+            delivery.delivered();
+        }
+        tickets
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn ticket(
+        plate: &str,
+        road: u16,
+        mile1: Mile,
+        timestamp1: Timestamp,
+        mile2: Mile,
+        timestamp2: Timestamp,
+        speed: u16,
+    ) -> Ticket {
+        Ticket {
+            plate: plate.into(),
+            road,
+            mile1,
+            timestamp1,
+            mile2,
+            timestamp2,
+            speed,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn out_of_order_observations() {
         let camera = |mile| Camera {
             road: 64782,
             mile,
             limit: 80,
         };
-        let plate = "AD58VWD".to_string();
-        let mut region = Region::new();
-        region.record_plate(camera(607), plate.clone(), 98270088);
-        region.record_plate(camera(485), plate.clone(), 98277449);
-        region.record_plate(camera(348), plate.clone(), 98285700);
-        region.record_plate(camera(883), plate.clone(), 98253436);
-        region.record_plate(camera(772), plate.clone(), 98260133);
-        region.record_plate(camera(3), plate.clone(), 98301294);
-        region.record_plate(camera(196), plate.clone(), 98294018);
-        sleep(Duration::from_millis(1000)).await;
+        let plate = "AD58VWD";
+        let observations = [
+            (camera(607), plate, 98270088),
+            (camera(485), plate, 98277449),
+            (camera(348), plate, 98285700),
+            (camera(883), plate, 98253436),
+            (camera(772), plate, 98260133),
+            (camera(3), plate, 98301294),
+            (camera(196), plate, 98294018),
+        ];
+        let tickets = dispatched(Region::default(), &[64782], &observations).await;
+        // Only the last leg, arriving after the rest, is over the limit.
+        assert_eq!(
+            vec![ticket("AD58VWD", 64782, 196, 98294018, 3, 98301294, 9549)],
+            tickets
+        );
     }
 
-    #[tokio::test]
-    #[traced_test]
-    async fn replicate_bug2() {
+    #[tokio::test(start_paused = true)]
+    async fn out_of_order_observations_on_a_busy_road() {
         let camera = |mile| Camera {
             road: 50753,
             mile,
             limit: 100,
         };
-        let plate = "GA96RKA".to_string();
-        let mut region = Region::new();
-        region.record_plate(camera(515), plate.clone(), 16117934);
-        region.record_plate(camera(353), plate.clone(), 16103360);
-        region.record_plate(camera(597), plate.clone(), 16125311);
-        region.record_plate(camera(353), plate.clone(), 16135870);
-        region.record_plate(camera(597), plate.clone(), 16127086);
-        region.record_plate(camera(123), plate.clone(), 16144150);
-        region.record_plate(camera(515), plate.clone(), 16130038);
-
-        sleep(Duration::from_millis(1000)).await;
+        let plate = "GA96RKA";
+        let observations = [
+            (camera(515), plate, 16117934),
+            (camera(353), plate, 16103360),
+            (camera(597), plate, 16125311),
+            (camera(353), plate, 16135870),
+            (camera(597), plate, 16127086),
+            (camera(123), plate, 16144150),
+            (camera(515), plate, 16130038),
+        ];
+        let tickets = dispatched(Region::default(), &[50753], &observations).await;
+        assert_eq!(Vec::<Ticket>::new(), tickets);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn multi_day_tickets() {
+        let camera = |mile| Camera {
+            road: 9,
+            mile,
+            limit: 60,
+        };
+        let observations = [
+            // 100 mph across midnight, ticketed for both days.
+            (camera(0), "M1DN1TE", 86400 - 180),
+            (camera(10), "M1DN1TE", 86400 + 180),
+            // Speeding later on the second day doesn't count again.
+            (camera(20), "M1DN1TE", 86400 + 360),
+            // Nor does speeding that ends on the first.
+            (camera(20), "M1DN1TE", 86400 - 720),
+            // But the third day is fair game.
+            (camera(0), "M1DN1TE", 2 * 86400),
+            (camera(10), "M1DN1TE", 2 * 86400 + 360),
+        ];
+        let tickets = dispatched(Region::default(), &[9], &observations).await;
+        assert_eq!(
+            vec![
+                ticket("M1DN1TE", 9, 0, 86220, 10, 86580, 10000),
+                ticket("M1DN1TE", 9, 0, 172800, 10, 173160, 10000),
+            ],
+            tickets
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duplicate_timestamps() {
+        let camera = |mile| Camera {
+            road: 4,
+            mile,
+            limit: 60,
+        };
+        let observations = [
+            (camera(0), "D0UBLE", 1000),
+            // The same observation again, which changes nothing.
+            (camera(0), "D0UBLE", 1000),
+            // Being in two places at once is inconsistent, so it's ignored.
+            (camera(50), "D0UBLE", 1000),
+            (camera(1), "D0UBLE", 1030),
+            (camera(1), "D0UBLE", 1030),
+        ];
+        let tickets = dispatched(Region::default(), &[4], &observations).await;
+        assert_eq!(vec![ticket("D0UBLE", 4, 0, 1000, 1, 1030, 12000)], tickets);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undelivered_tickets_go_to_another_dispatcher() {
+        let mut region = Region::default();
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([5]),
+        };
+        let mut first = region.register_dispatcher(dispatcher.clone());
+        let mut second = region.register_dispatcher(dispatcher);
+        let camera = |mile| Camera {
+            road: 5,
+            mile,
+            limit: 60,
+        };
+        region.record_plate(camera(0), "F41L".into(), 0);
+        region.record_plate(camera(1), "F41L".into(), 30);
+        // The first dispatcher's write fails, so it drops the ticket and
+        // goes away.
+        let delivery = first.recv().await.unwrap();
+        drop(delivery);
+        drop(first);
+        let ticket = deliver(&mut second).await;
+        assert_eq!("F41L", ticket.plate);
+        assert!(timeout(Duration::from_secs(60), second.recv())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tickets_held_by_departed_dispatcher_wait_for_the_next() {
+        let mut region = Region::default();
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([6]),
+        };
+        let mut departing = region.register_dispatcher(dispatcher.clone());
+        let camera = |mile| Camera {
+            road: 6,
+            mile,
+            limit: 60,
+        };
+        region.record_plate(camera(0), "G0NE".into(), 0);
+        region.record_plate(camera(1), "G0NE".into(), 30);
+        region.record_plate(camera(0), "G0NE".into(), 86400);
+        region.record_plate(camera(1), "G0NE".into(), 86430);
+        // One ticket is taken and the other waits in the channel when the
+        // dispatcher goes away.
+        let delivery = departing.recv().await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+        drop(departing);
+        drop(delivery);
+        let mut next = region.register_dispatcher(dispatcher);
+        let mut days = vec![
+            deliver(&mut next).await.timestamp1 / 86400,
+            deliver(&mut next).await.timestamp1 / 86400,
+        ];
+        days.sort();
+        assert_eq!(vec![0, 1], days);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_dispatcher_does_not_hold_up_the_rest() {
+        let mut region = Region::default();
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([9]),
+        };
+        // The first dispatcher never reads, so its channel fills up.
+        let _stalled = region.register_dispatcher(dispatcher.clone());
+        let mut live = region.register_dispatcher(dispatcher);
+        sleep(Duration::from_secs(1)).await;
+        let camera = |mile| Camera {
+            road: 9,
+            mile,
+            limit: 60,
+        };
+        for day in 0..4 {
+            region.record_plate(camera(0), "ST4LL".into(), day * 86400);
+            region.record_plate(camera(1), "ST4LL".into(), day * 86400 + 30);
+        }
+        // The stalled dispatcher holds one ticket, and the live one gets the
+        // rest.
+        for _ in 0..3 {
+            assert_eq!("ST4LL", deliver(&mut live).await.plate);
+        }
+        assert!(timeout(Duration::from_secs(60), live.recv()).await.is_err());
+    }
+
+    // Tickets one plate per call on road 8, so each is a separate ticket.
+    fn speed(region: &mut Region, plate: &str) {
+        let camera = |mile| Camera {
+            road: 8,
+            mile,
+            limit: 60,
+        };
+        region.record_plate(camera(0), plate.into(), 0);
+        region.record_plate(camera(1), plate.into(), 30);
+    }
+
+    fn distributed(distribution: Distribution) -> Region {
+        Region::new(
+            Journal::memory(),
+            Audit::none(),
+            Config {
+                distribution,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn round_robin_distribution() {
+        let mut region = distributed(Distribution::RoundRobin);
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([8]),
+        };
+        let mut dispatchers: Vec<_> = (0..3)
+            .map(|_| region.register_dispatcher(dispatcher.clone()))
+            .collect();
+        // Registration races the first ticket, so let it finish.
+        sleep(Duration::from_secs(1)).await;
+        for round in 0..2 {
+            for (i, tickets) in dispatchers.iter_mut().enumerate() {
+                let plate = format!("RR{}{}", round, i);
+                speed(&mut region, &plate);
+                assert_eq!(plate, deliver(tickets).await.plate);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn least_outstanding_distribution() {
+        let mut region = distributed(Distribution::LeastOutstanding);
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([8]),
+        };
+        let mut slow = region.register_dispatcher(dispatcher.clone());
+        let mut fast = region.register_dispatcher(dispatcher);
+        // The slow dispatcher takes the first ticket but never acknowledges
+        // it, so the fast one gets the rest.
+        speed(&mut region, "SL0W");
+        let held = slow.recv().await.unwrap();
+        assert_eq!("SL0W", held.ticket.plate);
+        for i in 0..3 {
+            let plate = format!("FA5T{}", i);
+            speed(&mut region, &plate);
+            assert_eq!(plate, deliver(&mut fast).await.plate);
+        }
+        // Once it catches up, it's back in the running.
+        held.delivered();
+        sleep(Duration::from_secs(1)).await;
+        speed(&mut region, "AGA1N");
+        assert_eq!("AGA1N", deliver(&mut slow).await.plate);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn round_robin_skips_full_dispatchers() {
+        let mut region = distributed(Distribution::RoundRobin);
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([8]),
+        };
+        let mut stalled = region.register_dispatcher(dispatcher.clone());
+        let mut dispatchers: Vec<_> = (0..2)
+            .map(|_| region.register_dispatcher(dispatcher.clone()))
+            .collect();
+        sleep(Duration::from_secs(1)).await;
+        // The first ticket fills the stalled dispatcher's channel, so the
+        // others take turns with the rest.
+        speed(&mut region, "ST4LL");
+        for round in 0..2 {
+            for (i, tickets) in dispatchers.iter_mut().enumerate() {
+                let plate = format!("SK1P{}{}", round, i);
+                speed(&mut region, &plate);
+                assert_eq!(plate, deliver(tickets).await.plate);
+            }
+        }
+        // Once it catches up, it's next in the rotation.
+        assert_eq!("ST4LL", deliver(&mut stalled).await.plate);
+        sleep(Duration::from_secs(1)).await;
+        speed(&mut region, "AGA1N");
+        assert_eq!("AGA1N", deliver(&mut stalled).await.plate);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn least_outstanding_skips_full_dispatchers() {
+        let mut region = distributed(Distribution::LeastOutstanding);
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([8]),
+        };
+        let _stalled = region.register_dispatcher(dispatcher.clone());
+        // The busier and the quieter dispatcher.
+        let mut dispatchers: Vec<_> = (0..2)
+            .map(|_| region.register_dispatcher(dispatcher.clone()))
+            .collect();
+        sleep(Duration::from_secs(1)).await;
+        // The stalled dispatcher's channel holds one ticket; the others take
+        // theirs but never acknowledge them.
+        speed(&mut region, "ST4LL");
+        let mut held = Vec::new();
+        for (plate, i) in [("BUSY0", 0), ("QU1ET", 1), ("BUSY1", 0)] {
+            speed(&mut region, plate);
+            let delivery = dispatchers[i].recv().await.unwrap();
+            assert_eq!(plate, delivery.ticket.plate);
+            held.push(delivery);
+        }
+        // The stalled dispatcher has the fewest outstanding but no room, so
+        // the ticket goes to the next fewest.
+        speed(&mut region, "N3XT");
+        assert_eq!("N3XT", deliver(&mut dispatchers[1]).await.plate);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_available_distribution() {
+        let mut region = Region::default();
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([8]),
+        };
+        let mut first = region.register_dispatcher(dispatcher.clone());
+        let mut second = region.register_dispatcher(dispatcher);
+        for i in 0..3 {
+            let plate = format!("F1RST{}", i);
+            speed(&mut region, &plate);
+            assert_eq!(plate, deliver(&mut first).await.plate);
+        }
+        assert!(timeout(Duration::from_secs(60), second.recv())
+            .await
+            .is_err());
+    }
+
+    // The events in the region's journal once it has settled.
+    async fn journaled(region: &Region, path: &TempPath) -> Vec<Event> {
+        settle(region).await;
+        Journal::open(path).unwrap().events().to_vec()
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[traced_test]
+    async fn restart_does_not_ticket_twice() {
+        let path = TempPath::new("restart.jsonl");
+        let camera = |mile| Camera {
+            road: 1,
+            mile,
+            limit: 60,
+        };
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([1]),
+        };
+        let mut region = Region::new(
+            Journal::open(&path).unwrap(),
+            Audit::none(),
+            Config::default(),
+        );
+        let mut tickets = region.register_dispatcher(dispatcher.clone());
+        region.record_plate(camera(0), "UN1X".into(), 0);
+        region.record_plate(camera(1), "UN1X".into(), 30);
+        let ticket = deliver(&mut tickets).await;
+        let events = journaled(&region, &path).await;
+        assert_eq!(4, events.len());
+        assert_eq!(Event::Issued(ticket.clone()), events[2]);
+        assert_eq!(Event::Dispatched(ticket), events[3]);
+
+        // Another speeding observation on the same day is not ticketed again.
+        let mut region = Region::new(
+            Journal::open(&path).unwrap(),
+            Audit::none(),
+            Config::default(),
+        );
+        let mut tickets = region.register_dispatcher(dispatcher);
+        region.record_plate(camera(2), "UN1X".into(), 60);
+        assert_eq!(5, journaled(&region, &path).await.len());
+        assert!(timeout(Duration::from_secs(60), tickets.recv())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[traced_test]
+    async fn restart_does_not_reissue_under_a_lenient_policy() {
+        let path = TempPath::new("lenient.jsonl");
+        let camera = |mile| Camera {
+            road: 3,
+            mile,
+            limit: 60,
+        };
+        let config = Config {
+            policy: TicketPolicy {
+                tickets_per_day: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let dispatcher = Dispatcher {
+            roads: BTreeSet::from([3]),
+        };
+        let mut region = Region::new(Journal::open(&path).unwrap(), Audit::none(), config);
+        let mut tickets = region.register_dispatcher(dispatcher.clone());
+        region.record_plate(camera(0), "TW0CE".into(), 0);
+        region.record_plate(camera(1), "TW0CE".into(), 30);
+        let first = deliver(&mut tickets).await;
+        assert_eq!(4, journaled(&region, &path).await.len());
+
+        // The replayed violation is the journaled ticket, not a second one,
+        // so the plate can still be ticketed once more today.
+        let mut region = Region::new(Journal::open(&path).unwrap(), Audit::none(), config);
+        let mut tickets = region.register_dispatcher(dispatcher);
+        region.record_plate(camera(2), "TW0CE".into(), 60);
+        let second = deliver(&mut tickets).await;
+        assert_eq!(ticket("TW0CE", 3, 1, 30, 2, 60, 12000), second);
+        assert_ne!(first, second);
+        region.record_plate(camera(3), "TW0CE".into(), 90);
+        assert!(timeout(Duration::from_secs(60), tickets.recv())
+            .await
+            .is_err());
+        let issued = journaled(&region, &path)
+            .await
+            .into_iter()
+            .filter(|event| matches!(event, Event::Issued(_)))
+            .count();
+        assert_eq!(2, issued);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[traced_test]
+    async fn restart_drops_observations_outside_retention() {
+        let path = TempPath::new("retention.jsonl");
+        let camera = |mile| Camera {
+            road: 4,
+            mile,
+            limit: 60,
+        };
+        let config = Config {
+            retention: Retention(3600),
+            ..Default::default()
+        };
+        let mut region = Region::new(Journal::open(&path).unwrap(), Audit::none(), config);
+        let mut tickets = region.register_dispatcher(Dispatcher {
+            roads: BTreeSet::from([4]),
+        });
+        region.record_plate(camera(0), "0LD".into(), 0);
+        region.record_plate(camera(1), "0LD".into(), 30);
+        deliver(&mut tickets).await;
+        // Let the ticket be journaled as dispatched before the next
+        // observation.
+        settle(&region).await;
+        region.record_plate(camera(0), "NEW".into(), 4000);
+        assert_eq!(5, journaled(&region, &path).await.len());
+
+        // The journal keeps the ticket but not the observations behind it.
+        let mut region = Region::new(Journal::open(&path).unwrap(), Audit::none(), config);
+        let events = Journal::open(&path).unwrap().events().to_vec();
+        assert_eq!(3, events.len());
+        assert!(matches!(&events[0], Event::Issued(ticket) if ticket.plate == "0LD"));
+        assert!(matches!(&events[1], Event::Dispatched(ticket) if ticket.plate == "0LD"));
+        assert!(matches!(&events[2], Event::Observed(obs) if obs.plate == "NEW"));
+        let mut tickets = region.register_dispatcher(Dispatcher {
+            roads: BTreeSet::from([4]),
+        });
+        region.record_plate(camera(1), "NEW".into(), 4030);
+        assert_eq!(
+            ticket("NEW", 4, 0, 4000, 1, 4030, 12000),
+            deliver(&mut tickets).await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[traced_test]
+    async fn restart_dispatches_undelivered_tickets() {
+        let path = TempPath::new("undelivered.jsonl");
+        let camera = |mile| Camera {
+            road: 2,
+            mile,
+            limit: 60,
+        };
+        let mut region = Region::new(
+            Journal::open(&path).unwrap(),
+            Audit::none(),
+            Config::default(),
+        );
+        region.record_plate(camera(0), "RE5TART".into(), 0);
+        region.record_plate(camera(1), "RE5TART".into(), 30);
+        assert_eq!(3, journaled(&region, &path).await.len());
+
+        let mut region = Region::new(
+            Journal::open(&path).unwrap(),
+            Audit::none(),
+            Config::default(),
+        );
+        let mut tickets = region.register_dispatcher(Dispatcher {
+            roads: BTreeSet::from([2]),
+        });
+        let ticket = deliver(&mut tickets).await;
+        assert_eq!("RE5TART", ticket.plate);
+        assert_eq!(12000, ticket.speed);
+    }
+
+    #[test]
+    fn evict_observations_outside_window() {
+        let mut records = Records::new(Retention(3600));
+        let observe = |records: &mut Records, plate: &str, mile, time| {
+            let obs = Observation {
+                camera: Camera {
+                    road: 1,
+                    mile,
+                    limit: 60,
+                },
+                plate: plate.into(),
+                time,
+            };
+            Region::record_observation(records, &obs, &TicketPolicy::default())
+        };
+        assert_eq!((None, None), observe(&mut records, "OLD", 0, 0));
+        assert_eq!((None, None), observe(&mut records, "NEAR", 0, 400));
+        assert_eq!((None, None), observe(&mut records, "NEW", 0, 4000));
+        assert_eq!(1, records.evicted);
+        assert!(!records.by_plate.contains_key("OLD"));
+        // The pair spans more than the window, so there's no ticket.
+        assert_eq!((None, None), observe(&mut records, "OLD", 200, 4000));
+        // The pair is just inside the window, so there is.
+        let (violation, _) = observe(&mut records, "NEAR", 100, 4000);
+        let violation = violation.unwrap();
+        assert_eq!(10000, violation.ticket.speed);
+        let [earlier, later] = violation.observations;
+        assert_eq!((0, 400), (earlier.camera.mile, earlier.time));
+        assert_eq!((100, 4000), (later.camera.mile, later.time));
+        // Observations already outside the window are dropped.
+        assert_eq!((None, None), observe(&mut records, "LATE", 0, 399));
+        assert_eq!(2, records.evicted);
+        assert!(!records.by_plate.contains_key("LATE"));
+        assert_eq!(2, records.by_time.len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[traced_test]
+    async fn region_counts_evictions() {
+        let mut region = Region::new(
+            Journal::memory(),
+            Audit::none(),
+            Config {
+                retention: Retention(60),
+                ..Default::default()
+            },
+        );
+        let camera = Camera {
+            road: 1,
+            mile: 0,
+            limit: 60,
+        };
+        for time in 0..10 {
+            region.record_plate(camera, "EV1CT".into(), time * 60);
+        }
+        settle(&region).await;
+        assert_eq!(8, region.evicted());
+    }
+
+    fn policed(policy: TicketPolicy) -> Region {
+        Region::new(
+            Journal::memory(),
+            Audit::none(),
+            Config {
+                policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_tolerance() {
+        let camera = |mile| Camera {
+            road: 5,
+            mile,
+            limit: 60,
+        };
+        let observations = [
+            // 80 mph is more than 10 over.
+            (camera(0), "FAST", 0),
+            (camera(1), "FAST", 45),
+            // 65.45 mph isn't.
+            (camera(0), "SL0W", 0),
+            (camera(1), "SL0W", 55),
+        ];
+        let region = policed(TicketPolicy {
+            tolerance: 10.0,
+            ..Default::default()
+        });
+        let tickets = dispatched(region, &[5], &observations).await;
+        assert_eq!(vec![ticket("FAST", 5, 0, 0, 1, 45, 8000)], tickets);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_day_length_and_offset() {
+        let camera = |mile| Camera {
+            road: 6,
+            mile,
+            limit: 60,
+        };
+        // Hour-long days starting half past the hour.
+        let observations = [
+            (camera(0), "H0URLY", 1810),
+            (camera(1), "H0URLY", 1840),
+            // Still the first day, so suppressed.
+            (camera(2), "H0URLY", 3500),
+            (camera(3), "H0URLY", 3530),
+            (camera(4), "H0URLY", 5400),
+            (camera(5), "H0URLY", 5430),
+        ];
+        let region = policed(TicketPolicy {
+            day_length: 3600,
+            day_offset: -1800,
+            ..Default::default()
+        });
+        let tickets = dispatched(region, &[6], &observations).await;
+        assert_eq!(
+            vec![
+                ticket("H0URLY", 6, 0, 1810, 1, 1840, 12000),
+                ticket("H0URLY", 6, 4, 5400, 5, 5430, 12000),
+            ],
+            tickets
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_tickets_per_day() {
+        let camera = |mile| Camera {
+            road: 7,
+            mile,
+            limit: 60,
+        };
+        let observations = [
+            (camera(0), "TW1CE", 0),
+            (camera(1), "TW1CE", 30),
+            (camera(2), "TW1CE", 60),
+            (camera(3), "TW1CE", 90),
+        ];
+        let region = policed(TicketPolicy {
+            tickets_per_day: 2,
+            ..Default::default()
+        });
+        let tickets = dispatched(region, &[7], &observations).await;
+        assert_eq!(
+            vec![
+                ticket("TW1CE", 7, 0, 0, 1, 30, 12000),
+                ticket("TW1CE", 7, 1, 30, 2, 60, 12000),
+            ],
+            tickets
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_min_interval() {
+        let camera = |mile| Camera {
+            road: 8,
+            mile,
+            limit: 60,
+        };
+        let observations = [
+            // Too close together to compare.
+            (camera(0), "BL1P", 0),
+            (camera(1), "BL1P", 30),
+            (camera(3), "BL1P", 120),
+        ];
+        let region = policed(TicketPolicy {
+            min_interval: 60,
+            ..Default::default()
+        });
+        let tickets = dispatched(region, &[8], &observations).await;
+        assert_eq!(vec![ticket("BL1P", 8, 1, 30, 3, 120, 8000)], tickets);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_min_interval_with_dense_observations() {
+        let camera = |mile| Camera {
+            road: 8,
+            mile,
+            limit: 60,
+        };
+        // Every neighbouring pair is too close, but the first and last
+        // aren't, and 3 miles in a minute is 180 mph.
+        let observations = [
+            (camera(0), "DEN5E", 0),
+            (camera(1), "DEN5E", 20),
+            (camera(2), "DEN5E", 40),
+            (camera(3), "DEN5E", 60),
+        ];
+        let region = policed(TicketPolicy {
+            min_interval: 60,
+            ..Default::default()
+        });
+        let tickets = dispatched(region, &[8], &observations).await;
+        assert_eq!(vec![ticket("DEN5E", 8, 0, 0, 3, 60, 18000)], tickets);
     }
 }
@@ -0,0 +1,317 @@
+use std::{
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::domain::{Observation, Ticket};
+
+// The events from which a region's state can be rebuilt, one per line of the
+// journal as json.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Observed(Observation),
+    Issued(Ticket),
+    // Written to a dispatcher's socket.
+    Dispatched(Ticket),
+}
+
+// An append-only file of events. Opening one reads the events already in it,
+// so a region can pick up where it left off.
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    events: Vec<Event>,
+}
+
+impl Journal {
+    // A journal that keeps nothing, so every region starts afresh.
+    pub fn memory() -> Self {
+        Default::default()
+    }
+
+    #[tracing::instrument(skip_all, fields(path = ?path.as_ref()))]
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (events, end) = read_complete_lines(path)?;
+        tracing::info!(events = events.len(), "opened journal");
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Anything after the last complete line is a torn write, which the
+        // next event would otherwise be glued onto.
+        if file.metadata()?.len() > end.length {
+            tracing::warn!(length = end.length, "truncating torn final line");
+            file.set_len(end.length)?;
+        }
+        if !end.terminated {
+            file.write_all(b"\n")?;
+        }
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            file: Some(file),
+            events,
+        })
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    // Forgets the events that aren't worth keeping, and rewrites the file
+    // without them, so it only grows as far as the region's state does. The
+    // new file replaces the old in one rename, so a crash leaves one or the
+    // other. If rewriting fails, we carry on appending to the old file.
+    #[tracing::instrument(skip_all, fields(path = ?self.path))]
+    pub(crate) fn compact(&mut self, keep: impl FnMut(&Event) -> bool) -> io::Result<()> {
+        let before = self.events.len();
+        self.events.retain(keep);
+        let path = match &self.path {
+            Some(path) if self.events.len() < before => path,
+            _ => return Ok(()),
+        };
+        let compacting = path.with_extension("compacting");
+        let mut file = File::create(&compacting)?;
+        for event in self.events.iter() {
+            let mut line = serde_json::to_vec(event).expect("serialize event");
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        file.sync_all()?;
+        fs::rename(&compacting, path)?;
+        self.file = Some(OpenOptions::new().append(true).open(path)?);
+        tracing::info!(before, after = self.events.len(), "compacted journal");
+        Ok(())
+    }
+
+    // Starts appending events to the file, returning a handle for the
+    // region's tasks to send them to.
+    pub(crate) fn start(self) -> Journaler {
+        let file = match self.file {
+            Some(file) => file,
+            None => return Journaler { events_tx: None },
+        };
+        Journaler {
+            events_tx: Some(write_lines(file)),
+        }
+    }
+}
+
+// Reads a file of json lines, which is empty if it doesn't exist yet.
+pub(crate) fn read_lines<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    Ok(read_complete_lines(path)?.0)
+}
+
+// Where the complete lines of a file end.
+#[derive(Debug)]
+struct End {
+    length: u64,
+    // Whether the last complete line has its newline.
+    terminated: bool,
+}
+
+fn read_complete_lines<T: DeserializeOwned>(path: &Path) -> io::Result<(Vec<T>, End)> {
+    let mut values = Vec::new();
+    let mut end = End {
+        length: 0,
+        terminated: true,
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((values, end)),
+        Err(err) => return Err(err),
+    };
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        let terminated = line.ends_with(b"\n");
+        match serde_json::from_slice(&line) {
+            Ok(value) => {
+                values.push(value);
+                end.length += read as u64;
+                end.terminated = terminated;
+            }
+            // We may have died partway through the last line.
+            Err(err) if reader.fill_buf()?.is_empty() => {
+                tracing::warn!(?err, "ignoring torn final line");
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+    Ok((values, end))
+}
+
+// Starts a thread appending the values sent to it to the file as json lines.
+// Each is written as it comes, so a crash loses at most the one being written.
+pub(crate) fn write_lines<T>(mut file: File) -> LineWriter<T>
+where
+    T: Serialize + Debug + Send + 'static,
+{
+    let (lines_tx, mut lines_rx) = mpsc::unbounded_channel::<Line<T>>();
+    thread::spawn(move || {
+        while let Some(line) = lines_rx.blocking_recv() {
+            let value = match line {
+                Line::Value(value) => value,
+                Line::Sync(synced) => {
+                    let _ = synced.send(());
+                    continue;
+                }
+            };
+            let mut line = serde_json::to_vec(&value).expect("serialize line");
+            line.push(b'\n');
+            if let Err(err) = file.write_all(&line) {
+                tracing::error!(?err, ?value, "writing line");
+                return;
+            }
+        }
+        tracing::info!("file closed");
+    });
+    LineWriter { lines_tx }
+}
+
+#[derive(Debug)]
+enum Line<T> {
+    Value(T),
+    // Asks the writer to say when it has written everything before this.
+    Sync(oneshot::Sender<()>),
+}
+
+// The sending end of a thread writing json lines.
+#[derive(Debug)]
+pub(crate) struct LineWriter<T> {
+    lines_tx: mpsc::UnboundedSender<Line<T>>,
+}
+
+// Derived, it would need T: Clone.
+impl<T> Clone for LineWriter<T> {
+    fn clone(&self) -> Self {
+        Self {
+            lines_tx: self.lines_tx.clone(),
+        }
+    }
+}
+
+impl<T> LineWriter<T> {
+    // Queues the value to be written, or returns it if the writer has stopped.
+    pub(crate) fn write(&self, value: T) -> Result<(), T> {
+        self.lines_tx
+            .send(Line::Value(value))
+            .map_err(|err| match err.0 {
+                Line::Value(value) => value,
+                Line::Sync(_) => unreachable!("sent a value"),
+            })
+    }
+
+    // Waits until everything queued so far is in the file, or the writer has
+    // stopped.
+    pub(crate) async fn sync(&self) {
+        let (synced_tx, synced_rx) = oneshot::channel();
+        if self.lines_tx.send(Line::Sync(synced_tx)).is_ok() {
+            let _ = synced_rx.await;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Journaler {
+    events_tx: Option<LineWriter<Event>>,
+}
+
+impl Journaler {
+    pub(crate) fn record(&self, event: Event) {
+        if let Some(events_tx) = &self.events_tx {
+            if let Err(event) = events_tx.write(event) {
+                tracing::error!(?event, "journal writer stopped");
+            }
+        }
+    }
+
+    // Waits until every event recorded so far is in the file.
+    pub(crate) async fn sync(&self) {
+        if let Some(events_tx) = &self.events_tx {
+            events_tx.sync().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{domain::Camera, testing::TempPath};
+
+    use super::*;
+
+    #[test]
+    fn open_truncates_torn_final_event() {
+        let path = TempPath::new("torn.jsonl");
+        let ticket = Ticket {
+            plate: "T0RN".into(),
+            road: 3,
+            mile1: 0,
+            timestamp1: 0,
+            mile2: 1,
+            timestamp2: 30,
+            speed: 12000,
+        };
+        let mut contents = serde_json::to_string(&Event::Issued(ticket.clone())).unwrap();
+        contents.push_str("\n{\"dispatched\":{\"plate\":");
+        fs::write(&path, contents).unwrap();
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(&[Event::Issued(ticket.clone())], journal.events());
+
+        // What's appended next survives the following restart.
+        let mut line = serde_json::to_vec(&Event::Dispatched(ticket.clone())).unwrap();
+        line.push(b'\n');
+        journal.file.unwrap().write_all(&line).unwrap();
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(
+            &[Event::Issued(ticket.clone()), Event::Dispatched(ticket)],
+            journal.events()
+        );
+    }
+
+    #[tokio::test]
+    async fn compact_rewrites_kept_events() {
+        let path = TempPath::new("compact.jsonl");
+        let observed = |time| {
+            Event::Observed(Observation {
+                camera: Camera {
+                    road: 1,
+                    mile: 0,
+                    limit: 60,
+                },
+                plate: "C0MPACT".into(),
+                time,
+            })
+        };
+        let contents: String = [observed(0), observed(100), observed(200)]
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap() + "\n")
+            .collect();
+        fs::write(&path, contents).unwrap();
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .compact(|event| !matches!(event, Event::Observed(obs) if obs.time < 100))
+            .unwrap();
+        assert_eq!(&[observed(100), observed(200)], journal.events());
+        // Appending carries on in the new file.
+        let journaler = journal.start();
+        journaler.record(observed(300));
+        journaler.sync().await;
+        assert_eq!(
+            vec![observed(100), observed(200), observed(300)],
+            read_lines::<Event>(&path).unwrap()
+        );
+    }
+}
@@ -0,0 +1,10 @@
+pub mod audit;
+pub mod codec;
+pub mod connection;
+pub mod domain;
+pub mod journal;
+pub mod policy;
+pub mod server;
+
+#[cfg(test)]
+mod testing;
@@ -1,10 +1,12 @@
-use server::Server;
+use speed::{
+    audit::Audit,
+    domain::{Config, Region, Retention},
+    journal::Journal,
+    policy::TicketPolicy,
+    server::Server,
+};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 
-pub mod connection;
-pub mod domain;
-pub mod server;
-
 #[tokio::main(flavor = "multi_thread")]
 #[tracing::instrument]
 async fn main() {
@@ -14,6 +16,34 @@ async fn main() {
         .with_writer(file_appender)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("configure tracing");
-    let mut server = Server::new();
+    // State only survives restarts when there's somewhere to keep it.
+    let journal = match std::env::var_os("SPEED_JOURNAL") {
+        Some(path) => Journal::open(path).expect("open journal"),
+        None => Journal::memory(),
+    };
+    let audit = match std::env::var_os("SPEED_AUDIT") {
+        Some(path) => Audit::open(path).expect("open audit log"),
+        None => Audit::none(),
+    };
+    // Observations are kept forever unless given a window in seconds.
+    let retention = match std::env::var("SPEED_RETENTION") {
+        Ok(seconds) => Retention(seconds.parse().expect("parse retention seconds")),
+        Err(_) => Retention::default(),
+    };
+    let distribution = match std::env::var("SPEED_DISTRIBUTION") {
+        Ok(distribution) => distribution.parse().expect("parse distribution"),
+        Err(_) => Default::default(),
+    };
+    // The protocol's ticketing rules, unless a json file says otherwise.
+    let policy = match std::env::var_os("SPEED_POLICY") {
+        Some(path) => TicketPolicy::load(path).expect("load ticket policy"),
+        None => TicketPolicy::default(),
+    };
+    let config = Config {
+        retention,
+        distribution,
+        policy,
+    };
+    let mut server = Server::new(Region::new(journal, audit, config));
     server.run().await.unwrap();
 }
@@ -0,0 +1,117 @@
+use std::{fs::File, io, ops::RangeInclusive, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::{Speed, Ticket, Timestamp};
+
+// A day in the policy's time zone, counted from the one holding the epoch.
+// Days before it are negative.
+pub type Day = i64;
+
+// The rules by which speeding becomes a ticket. The defaults are the
+// protocol's: more than half a mph over the limit, and one ticket per plate
+// per UTC day.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TicketPolicy {
+    // How many mph over the limit a plate may go without a ticket.
+    pub tolerance: f64,
+    // The seconds in a day, for the per-day rule.
+    pub day_length: u32,
+    // The seconds to add to a timestamp to put it in the local day, so -3600
+    // for days that start an hour after UTC midnight.
+    pub day_offset: i32,
+    // How many tickets a plate may get on any one day.
+    pub tickets_per_day: u32,
+    // Observations closer together than this many seconds aren't compared,
+    // as their timing is too coarse to judge speed.
+    pub min_interval: u32,
+}
+
+impl Default for TicketPolicy {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.5,
+            day_length: 86400,
+            day_offset: 0,
+            tickets_per_day: 1,
+            min_interval: 0,
+        }
+    }
+}
+
+impl TicketPolicy {
+    // Reads a policy from a json file, taking the defaults for any settings
+    // it leaves out.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let policy: Self = serde_json::from_reader(File::open(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let invalid = |message| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        if policy.day_length == 0 {
+            return invalid("day_length must be positive");
+        }
+        if policy.tickets_per_day == 0 {
+            return invalid("tickets_per_day must be positive");
+        }
+        if !(policy.tolerance.is_finite() && policy.tolerance >= 0.0) {
+            return invalid("tolerance must be a number of mph, at least 0");
+        }
+        Ok(policy)
+    }
+
+    pub fn day(&self, time: Timestamp) -> Day {
+        // Neither can overflow an i64.
+        let local = i64::from(time) + i64::from(self.day_offset);
+        local.div_euclid(i64::from(self.day_length))
+    }
+
+    // The days on which the ticket counts against the plate.
+    pub fn days(&self, ticket: &Ticket) -> RangeInclusive<Day> {
+        self.day(ticket.timestamp1)..=self.day(ticket.timestamp2)
+    }
+
+    pub fn speeding(&self, speed: f64, limit: Speed) -> bool {
+        speed - self.tolerance > limit.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::testing::TempPath;
+
+    use super::*;
+
+    #[test]
+    fn load_policy() {
+        let path = TempPath::new("policy.json");
+        fs::write(&path, r#"{"tolerance": 2.0, "day_offset": -3600}"#).unwrap();
+        let policy = TicketPolicy::load(&path).unwrap();
+        assert_eq!(
+            TicketPolicy {
+                tolerance: 2.0,
+                day_offset: -3600,
+                ..Default::default()
+            },
+            policy
+        );
+        assert_eq!(0, policy.day(86400 + 3599));
+        assert_eq!(1, policy.day(86400 + 3600));
+        // The first hour belongs to the day before the epoch's.
+        assert_eq!(-1, policy.day(0));
+        assert_eq!(-1, policy.day(3599));
+
+        for invalid in [
+            r#"{"tolerence": 2.0}"#,
+            r#"{"day_length": 0}"#,
+            r#"{"tickets_per_day": 0}"#,
+            r#"{"tolerance": -1.0}"#,
+            r#"{"day_offset": 2147483648}"#,
+        ] {
+            fs::write(&path, invalid).unwrap();
+            let err = TicketPolicy::load(&path).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind(), "{}", invalid);
+        }
+    }
+}
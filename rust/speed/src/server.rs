@@ -8,7 +8,7 @@ use tokio::{
 
 use crate::{
     connection::{Connection, Message},
-    domain::{Camera, Dispatcher, Plate, Region, Ticket, Timestamp},
+    domain::{Camera, Delivery, Dispatcher, LimitConflict, Plate, Region, Timestamp},
 };
 
 #[derive(Debug)]
@@ -16,18 +16,29 @@ pub struct Server {
     region: Region,
 }
 
+impl Default for Server {
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
+}
+
 impl Server {
-    pub fn new() -> Self {
-        Self {
-            region: Region::new(),
-        }
+    pub fn new(region: Region) -> Self {
+        Self { region }
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn run(&mut self) -> Result<(), io::Error> {
+        let listener = TcpListener::bind("0.0.0.0:9000").await?;
+        self.serve(listener).await
+    }
+
+    // Serves clients from the listener, which lets tests and the simulator
+    // run a server on any port.
+    #[tracing::instrument(skip_all)]
+    pub async fn serve(&mut self, listener: TcpListener) -> Result<(), io::Error> {
         tracing::info!("starting server");
         let (tx, mut rx) = mpsc::channel::<ServerCommand>(16);
-        let listener = TcpListener::bind("0.0.0.0:9000").await?;
         loop {
             tokio::select! {
                 Ok((socket, _)) = listener.accept() => {
@@ -40,6 +51,11 @@ impl Server {
                 }
                 Some(cmd) = rx.recv() => {
                     match cmd {
+                        ServerCommand::RegisterCamera(camera, tx) => {
+                            if let Err(err) = tx.send(self.region.register_camera(camera)) {
+                                tracing::error!(?err, ?camera, "registering camera");
+                            }
+                        }
                         ServerCommand::RecordPlate(camera, plate, timestamp) => {
                             self.region.record_plate(camera, plate, timestamp);
                         }
@@ -57,8 +73,9 @@ impl Server {
 
 #[derive(Debug)]
 enum ServerCommand {
+    RegisterCamera(Camera, oneshot::Sender<Result<(), LimitConflict>>),
     RecordPlate(Camera, Plate, Timestamp),
-    RegisterDispatcher(Dispatcher, oneshot::Sender<mpsc::Receiver<Ticket>>),
+    RegisterDispatcher(Dispatcher, oneshot::Sender<mpsc::Receiver<Delivery>>),
 }
 
 async fn send_error(mut conn: Connection<'_>, msg: &str) -> Result<(), io::Error> {
@@ -83,9 +100,11 @@ async fn maybe_tick(interval: &mut Option<Option<Interval>>) -> Option<()> {
 async fn handle(mut socket: TcpStream, tx: mpsc::Sender<ServerCommand>) -> Result<(), io::Error> {
     let mut heartbeat: Option<Option<Interval>> = None;
     let mut conn = Connection::new(&mut socket);
+    // Once the client stops sending, we only owe it heartbeats and tickets.
+    let mut reading = true;
     loop {
         tokio::select! {
-            msg = conn.read_message() => {
+            msg = conn.read_message(), if reading => {
                 match msg {
                     Ok(Message::WantHeartbeat(duration)) => {
                         if heartbeat.is_some() {
@@ -98,12 +117,29 @@ async fn handle(mut socket: TcpStream, tx: mpsc::Sender<ServerCommand>) -> Resul
                         }
                     }
                     Ok(Message::IAmCamera(camera)) => {
+                        let (registered_tx, registered_rx) = oneshot::channel();
+                        let cmd = ServerCommand::RegisterCamera(camera, registered_tx);
+                        if let Err(err) = tx.send(cmd).await {
+                            tracing::error!(?err, "failed to register camera");
+                            return Ok(());
+                        }
+                        match registered_rx.await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(conflict)) => {
+                                tracing::warn!(?camera, %conflict, "conflicting camera");
+                                return send_error(conn, &conflict.to_string()).await;
+                            }
+                            Err(err) => {
+                                tracing::error!(?err, "failed to receive camera registration");
+                                return Ok(());
+                            }
+                        }
                         return handle_camera(conn, tx, camera, heartbeat).await;
                     }
                     Ok(Message::IAmDispatcher(dispatcher)) => {
                         return handle_dispatcher(conn, tx, dispatcher, heartbeat).await;
                     }
-                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => reading = false,
                     _ => {
                         return send_error(conn, "invalid message").await;
                     }
@@ -112,6 +148,8 @@ async fn handle(mut socket: TcpStream, tx: mpsc::Sender<ServerCommand>) -> Resul
             Some(_) = maybe_tick(&mut heartbeat) => {
                 conn.write_message(&Message::Heartbeat).await?;
             }
+            // The client is done and wants no heartbeats.
+            else => return Ok(()),
         }
     }
 }
@@ -123,9 +161,10 @@ async fn handle_camera(
     camera: Camera,
     mut heartbeat: Option<Option<Interval>>,
 ) -> Result<(), io::Error> {
+    let mut reading = true;
     loop {
         tokio::select! {
-            msg = conn.read_message() => {
+            msg = conn.read_message(), if reading => {
                 match msg {
                     Ok(Message::WantHeartbeat(duration)) => {
                         if heartbeat.is_some() {
@@ -143,7 +182,7 @@ async fn handle_camera(
                             tracing::error!(?err, "dropped plate record");
                         }
                     }
-                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => reading = false,
                     _ => {
                         return send_error(conn, "invalid camera message").await;
                     }
@@ -152,6 +191,8 @@ async fn handle_camera(
             Some(_) = maybe_tick(&mut heartbeat) => {
                 conn.write_message(&Message::Heartbeat).await?;
             }
+            // The client is done and wants no heartbeats.
+            else => return Ok(()),
         }
     }
 }
@@ -166,10 +207,11 @@ async fn handle_dispatcher(
     // This copy of the loop is just to handle the case where roads is empty, and therefore
     // the tickets_rx will always be closed/ing because there will be no tickets_tx stored
     // in the road dispatchers collection.
+    let mut reading = true;
     if dispatcher.roads.is_empty() {
         loop {
             tokio::select! {
-                msg = conn.read_message() => {
+                msg = conn.read_message(), if reading => {
                     match msg {
                         Ok(Message::WantHeartbeat(duration)) => {
                             if heartbeat.is_some() {
@@ -181,7 +223,7 @@ async fn handle_dispatcher(
                                 heartbeat = Some(None);
                             }
                         }
-                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => reading = false,
                         _ => {
                             return send_error(conn, "invalid dispatcher message").await;
                         }
@@ -190,6 +232,7 @@ async fn handle_dispatcher(
                 Some(_) = maybe_tick(&mut heartbeat) => {
                     conn.write_message(&Message::Heartbeat).await?;
                 }
+                else => return Ok(()),
             }
         }
     } else {
@@ -207,19 +250,22 @@ async fn handle_dispatcher(
         let mut ticket_rx = ticket_rx.unwrap();
         loop {
             tokio::select! {
-                ticket = ticket_rx.recv() => {
-                    if ticket.is_none() {
+                delivery = ticket_rx.recv() => {
+                    if delivery.is_none() {
                         tracing::error!("ticket channel closed");
                         return Ok(());
                     }
-                    let ticket = ticket.unwrap();
+                    let delivery = delivery.unwrap();
+                    let ticket = &delivery.ticket;
                     tracing::info!(?ticket, "writing ticket");
+                    // Dropping the delivery on error hands the ticket back.
                     if let Err(err) = conn.write_message(&Message::Ticket(ticket.clone())).await {
                         tracing::error!(?ticket, ?err, "error writing ticket, closing dispatcher");
                         return Err(err);
                     }
+                    delivery.delivered();
                 }
-                msg = conn.read_message() => {
+                msg = conn.read_message(), if reading => {
                     match msg {
                         Ok(Message::WantHeartbeat(duration)) => {
                             if heartbeat.is_some() {
@@ -231,7 +277,7 @@ async fn handle_dispatcher(
                                 heartbeat = Some(None);
                             }
                         }
-                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => reading = false,
                         _ => {
                             return send_error(conn, "invalid dispatcher message").await;
                         }
@@ -240,7 +286,62 @@ async fn handle_dispatcher(
                 Some(_) = maybe_tick(&mut heartbeat) => {
                     conn.write_message(&Message::Heartbeat).await?;
                 }
+                else => return Ok(()),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::timeout,
+    };
+    use tokio_util::codec::Encoder;
+
+    use crate::codec::MessageCodec;
+
+    use super::*;
+
+    async fn connect() -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { Server::default().serve(listener).await });
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    // Half-closes the client, then waits for the server to close its side,
+    // which it only does once the handler has returned.
+    async fn hang_up(mut client: TcpStream) {
+        client.shutdown().await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = timeout(Duration::from_secs(1), client.read(&mut buf))
+            .await
+            .expect("handler returns")
+            .unwrap();
+        assert_eq!(0, read);
+    }
+
+    #[tokio::test]
+    async fn handler_returns_when_client_hangs_up() {
+        hang_up(connect().await).await;
+    }
+
+    #[tokio::test]
+    async fn camera_handler_returns_when_client_hangs_up() {
+        let mut client = connect().await;
+        let mut buf = BytesMut::new();
+        let camera = Message::IAmCamera(Camera {
+            road: 1,
+            mile: 0,
+            limit: 60,
+        });
+        MessageCodec.encode(&camera, &mut buf).unwrap();
+        client.write_all(&buf).await.unwrap();
+        hang_up(client).await;
+    }
+}
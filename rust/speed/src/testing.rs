@@ -0,0 +1,52 @@
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::time::sleep;
+
+use crate::domain::Region;
+
+// A file for one test in the temp directory, which is removed before the test
+// uses it and again once the test is done with it, passed or failed.
+#[derive(Debug)]
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    // The name must be unique among the tests, which run concurrently.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("speed-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Waits until the region's tasks are idle and everything they've journaled
+// or audited is in the files. The clock must be paused, so the sleep only
+// ends once every task is waiting on something else.
+pub(crate) async fn settle(region: &Region) {
+    sleep(Duration::from_secs(1)).await;
+    region.sync().await;
+}